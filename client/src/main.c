@@ -39,7 +39,12 @@ enum Command
   LoadTiles,
   SetBackgroundTiles,
   SetSpriteTile,
-  MoveSprite
+  MoveSprite,
+  PlaySound,
+  LoadWave,
+  StopSound,
+  Reset,
+  SetPalettes
 };
 
 void command_draw_text()
@@ -132,6 +137,68 @@ void command_move_sprite()
   move_sprite(sprite_index, x, y);
 }
 
+void command_play_sound()
+{
+  uint8_t channel = receive(); // 1-4
+
+  // Each channel has 5 registers from NR10, the last one triggers the sound
+  for (uint8_t i = 0; i < 5; ++i)
+  {
+    _IO[0x10 + (channel - 1) * 5 + i] = receive();
+  }
+}
+
+void command_load_wave()
+{
+  // The wave RAM can only be written while the channel is off
+  NR30_REG = 0;
+
+  for (uint8_t i = 0; i < 16; ++i)
+  {
+    _IO[0x30 + i] = receive();
+  }
+}
+
+void command_stop_sound()
+{
+  uint8_t channel = receive(); // 1-4
+
+  // Turning the DAC off silences the channel
+  if (channel == 3)
+  {
+    NR30_REG = 0;
+  }
+  else
+  {
+    _IO[0x10 + (channel - 1) * 5 + 2] = 0;
+  }
+}
+
+// Blank screen for the next app, sounds are left playing
+void command_reset()
+{
+  static const uint8_t blank_tile[16] = { 0 };
+
+  set_bkg_data(0, 1, blank_tile);
+  fill_bkg_rect(0, 0, 32, 32, 0);
+  move_bkg(0, 0);
+
+  for (uint8_t i = 0; i < 40; ++i)
+  {
+    move_sprite(i, 0, 0);
+  }
+
+  BGP_REG = 0xE4;
+  OBP0_REG = 0xE4;
+}
+
+// Shades of the background and sprites, eg. for fades
+void command_set_palettes()
+{
+  BGP_REG = receive();
+  OBP0_REG = receive();
+}
+
 void send_inputs()
 {
   send(joypad());
@@ -154,6 +221,11 @@ void receive_commands()
       case SetBackgroundTiles: command_set_background_tiles(); break;
       case SetSpriteTile: command_set_sprite_tile(); break;
       case MoveSprite: command_move_sprite(); break;
+      case PlaySound: command_play_sound(); break;
+      case LoadWave: command_load_wave(); break;
+      case StopSound: command_stop_sound(); break;
+      case Reset: command_reset(); break;
+      case SetPalettes: command_set_palettes(); break;
 
       default:
         printf("unknown command id: %d\n", command_id);
@@ -177,6 +249,15 @@ void main()
   SHOW_BKG;
   SHOW_SPRITES;
 
+  // Identity palettes, sprite color 0 being transparent
+  BGP_REG = 0xE4;
+  OBP0_REG = 0xE4;
+
+  // Enable the APU with all channels on both speakers
+  NR52_REG = 0x80;
+  NR50_REG = 0x77;
+  NR51_REG = 0xFF;
+
   send(SYSTEM_ID);
 
   while (1)
@@ -1,14 +1,406 @@
 pub mod bouncing_balls;
+pub mod calibrate;
 pub mod display_image;
 pub mod fill_screens;
+pub mod ingest;
+pub mod markers;
+pub mod marquee;
+pub mod pong;
+pub mod script;
 pub mod show_info;
+pub mod snake;
+pub mod video;
 
+use std::collections::HashMap;
 use std::time::Duration;
 
+use clap::ValueEnum;
+
 use crate::clients::client::Client;
+use crate::clients::input::InputEvent;
+use crate::engine::dithering::{Conversion, Dithering};
+use crate::engine::font::Font;
 use crate::ServerCommand;
 
+use bouncing_balls::BouncingBallsApp;
+use calibrate::CalibrateApp;
+use display_image::DisplayImageApp;
+use fill_screens::FillScreensApp;
+use ingest::IngestApp;
+use marquee::MarqueeApp;
+use pong::PongApp;
+use script::ScriptApp;
+use show_info::ShowInfoApp;
+use snake::SnakeApp;
+use video::VideoApp;
+
 pub trait App {
+    // The screens were reset before the app starts, and after it stops
+    fn on_start(&mut self, _clients: &mut Vec<Client>) {}
+    fn on_stop(&mut self, _clients: &mut Vec<Client>) {}
+
+    // Clients connecting or leaving while the app runs
+    fn on_client_joined(&mut self, _client: &mut Client) {}
+    fn on_client_left(&mut self, _client_id: u8) {}
+
     fn update(&mut self, _dt: &Duration, _clients: &mut Vec<Client>) {}
+    fn on_input(&mut self, _client_id: u8, _event: &InputEvent) {}
     fn process_server_command(&mut self, _command: &ServerCommand) {}
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgumentKind {
+    Text,
+    Integer,
+    Number,
+    Flag,
+}
+
+pub struct ArgumentSpec {
+    pub name: &'static str,
+    pub kind: ArgumentKind,
+    pub description: &'static str,
+    pub required: bool,
+    pub default: Option<&'static str>,
+}
+
+// App that can be started from the console, eg. app image path=foo.png
+pub struct AppSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub arguments: &'static [ArgumentSpec],
+    pub create: fn(&AppArguments) -> Result<Box<dyn App>, String>,
+}
+
+// Arguments checked against the app's specs, with their defaults
+pub struct AppArguments {
+    values: HashMap<&'static str, String>,
+}
+
+impl AppArguments {
+    // None for the optional arguments without default that were not given
+    pub fn text(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    pub fn integer(&self, name: &str) -> Option<i64> {
+        self.text(name).and_then(|value| value.parse().ok())
+    }
+
+    pub fn number(&self, name: &str) -> Option<f32> {
+        self.text(name).and_then(|value| value.parse().ok())
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.text(name).and_then(parse_flag).unwrap_or(false)
+    }
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    match value {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+// Every app, in the order they are listed on the console
+pub const APPS: &[AppSpec] = &[
+    AppSpec {
+        name: "info",
+        description: "Show the position of each client",
+        arguments: &[],
+        create: |_| Ok(Box::new(ShowInfoApp::new())),
+    },
+    AppSpec {
+        name: "fill",
+        description: "Fill and erase the screens tile by tile",
+        arguments: &[],
+        create: |_| Ok(Box::new(FillScreensApp::new())),
+    },
+    AppSpec {
+        name: "balls",
+        description: "Balls bouncing across the screens, A to add one",
+        arguments: &[ArgumentSpec {
+            name: "count",
+            kind: ArgumentKind::Integer,
+            description: "Balls at the start, up to 40",
+            required: false,
+            default: Some("1"),
+        }],
+        create: |arguments| {
+            let count = arguments.integer("count").unwrap_or(1);
+
+            if !(1..=40).contains(&count) {
+                return Err(String::from("count must be between 1 and 40"));
+            }
+
+            Ok(Box::new(BouncingBallsApp::with_balls(count as usize)))
+        },
+    },
+    AppSpec {
+        name: "pong",
+        description: "Pong across the wall",
+        arguments: &[],
+        create: |_| Ok(Box::new(PongApp::new())),
+    },
+    AppSpec {
+        name: "snake",
+        description: "Multiplayer snake on the whole wall",
+        arguments: &[],
+        create: |_| Ok(Box::new(SnakeApp::new())),
+    },
+    AppSpec {
+        name: "marquee",
+        description: "Text scrolling across the wall",
+        arguments: &[
+            ArgumentSpec {
+                name: "text",
+                kind: ArgumentKind::Text,
+                description: "Text to scroll, with _ for spaces",
+                required: false,
+                default: None,
+            },
+            ArgumentSpec {
+                name: "font",
+                kind: ArgumentKind::Text,
+                description: "BDF font, or PNG grid of the ASCII glyphs from space to ~",
+                required: false,
+                default: None,
+            },
+            ArgumentSpec {
+                name: "glyph_width",
+                kind: ArgumentKind::Integer,
+                description: "Width of the glyph cells of a PNG font",
+                required: false,
+                default: Some("8"),
+            },
+            ArgumentSpec {
+                name: "glyph_height",
+                kind: ArgumentKind::Integer,
+                description: "Height of the glyph cells of a PNG font",
+                required: false,
+                default: Some("8"),
+            },
+        ],
+        create: |arguments| {
+            let mut app = MarqueeApp::new();
+
+            if let Some(path) = arguments.text("font") {
+                let glyph_size = |name| {
+                    u8::try_from(arguments.integer(name).unwrap_or(8))
+                        .map_err(|_| format!("{name} must be between 0 and 255"))
+                };
+
+                app.set_font(Font::load(
+                    path,
+                    glyph_size("glyph_width")?,
+                    glyph_size("glyph_height")?,
+                )?);
+            }
+
+            if let Some(text) = arguments.text("text") {
+                app.set_text(&text.replace('_', " "));
+            }
+
+            Ok(Box::new(app))
+        },
+    },
+    AppSpec {
+        name: "image",
+        description: "Image spread on the wall",
+        arguments: &[
+            ArgumentSpec {
+                name: "path",
+                kind: ArgumentKind::Text,
+                description: "Image file",
+                required: false,
+                default: Some("test.png"),
+            },
+            ArgumentSpec {
+                name: "dither",
+                kind: ArgumentKind::Text,
+                description: "threshold, bayer, floyd-steinberg or atkinson",
+                required: false,
+                default: Some("threshold"),
+            },
+            ArgumentSpec {
+                name: "gamma",
+                kind: ArgumentKind::Number,
+                description: "Gamma applied before the conversion",
+                required: false,
+                default: Some("1.0"),
+            },
+            ArgumentSpec {
+                name: "contrast",
+                kind: ArgumentKind::Number,
+                description: "Contrast applied before the conversion",
+                required: false,
+                default: Some("1.0"),
+            },
+        ],
+        create: |arguments| {
+            let mut app = DisplayImageApp::new(arguments.text("path").unwrap_or(""))?;
+
+            let dither = arguments.text("dither").unwrap_or("");
+            let dithering = Dithering::from_str(dither, true)
+                .map_err(|_| format!("unknown dithering {dither}"))?;
+
+            app.set_conversion(Conversion {
+                dithering,
+                gamma: arguments.number("gamma").unwrap_or(1.0).max(0.01),
+                contrast: arguments.number("contrast").unwrap_or(1.0),
+                ..Default::default()
+            });
+
+            Ok(Box::new(app))
+        },
+    },
+    AppSpec {
+        name: "video",
+        description: "GIF or numbered frames played on the wall",
+        arguments: &[
+            ArgumentSpec {
+                name: "path",
+                kind: ArgumentKind::Text,
+                description: "GIF file or directory of frames, or load it with video play",
+                required: false,
+                default: None,
+            },
+            ArgumentSpec {
+                name: "fps",
+                kind: ArgumentKind::Number,
+                description: "Framerate overriding the GIF's",
+                required: false,
+                default: None,
+            },
+            ArgumentSpec {
+                name: "loop",
+                kind: ArgumentKind::Flag,
+                description: "Start over at the end, or stay on the last frame",
+                required: false,
+                default: Some("true"),
+            },
+        ],
+        create: |arguments| {
+            let mut app = VideoApp::new();
+            app.set_looping(arguments.flag("loop"));
+
+            if let Some(path) = arguments.text("path") {
+                app.load(path, arguments.number("fps"));
+            }
+
+            Ok(Box::new(app))
+        },
+    },
+    AppSpec {
+        name: "ingest",
+        description: "Raw frames from a socket or a pipe, see ingest listen/pipe",
+        arguments: &[],
+        create: |_| Ok(Box::new(IngestApp::new())),
+    },
+    AppSpec {
+        name: "calibrate",
+        description: "Move, rotate and mirror each screen with its joypad",
+        arguments: &[],
+        create: |_| Ok(Box::new(CalibrateApp::new())),
+    },
+    AppSpec {
+        name: "script",
+        description: "App written in Rhai, reloaded when saved",
+        arguments: &[ArgumentSpec {
+            name: "path",
+            kind: ArgumentKind::Text,
+            description: "Script file",
+            required: true,
+            default: None,
+        }],
+        create: |arguments| {
+            Ok(Box::new(ScriptApp::new(
+                arguments.text("path").unwrap_or(""),
+            )))
+        },
+    },
+];
+
+// Arguments are name=value, or values in the order of the specs
+pub fn create_app(name: &str, arguments: &[String]) -> Result<Box<dyn App>, String> {
+    let spec = APPS
+        .iter()
+        .find(|spec| spec.name == name)
+        .ok_or_else(|| format!("unknown app {name}"))?;
+
+    let mut values = HashMap::new();
+
+    for (index, argument) in arguments.iter().enumerate() {
+        let (argument_spec, value) = match argument.split_once('=') {
+            Some((argument_name, value)) => (
+                spec.arguments
+                    .iter()
+                    .find(|argument_spec| argument_spec.name == argument_name)
+                    .ok_or_else(|| format!("{name} has no argument {argument_name}"))?,
+                value,
+            ),
+            None => (
+                spec.arguments
+                    .get(index)
+                    .ok_or_else(|| format!("{name} has no argument {}", index + 1))?,
+                argument.as_str(),
+            ),
+        };
+
+        let valid = match argument_spec.kind {
+            ArgumentKind::Text => true,
+            ArgumentKind::Integer => value.parse::<i64>().is_ok(),
+            ArgumentKind::Number => value.parse::<f32>().is_ok(),
+            ArgumentKind::Flag => parse_flag(value).is_some(),
+        };
+
+        if !valid {
+            return Err(format!(
+                "{} must be {:?}, got {}",
+                argument_spec.name, argument_spec.kind, value
+            ));
+        }
+
+        values.insert(argument_spec.name, value.to_string());
+    }
+
+    for argument_spec in spec.arguments {
+        if !values.contains_key(argument_spec.name) {
+            if argument_spec.required {
+                return Err(format!("{name} needs {}", argument_spec.name));
+            }
+
+            if let Some(default) = argument_spec.default {
+                values.insert(argument_spec.name, default.to_string());
+            }
+        }
+    }
+
+    (spec.create)(&AppArguments { values })
+}
+
+pub fn print_apps() {
+    for spec in APPS {
+        println!("{:<10} {}", spec.name, spec.description);
+
+        for argument_spec in spec.arguments {
+            println!(
+                "  {}={:?}{}{} {}",
+                argument_spec.name,
+                argument_spec.kind,
+                if argument_spec.required {
+                    " required"
+                } else {
+                    ""
+                },
+                argument_spec
+                    .default
+                    .map(|default| format!(" ({default})"))
+                    .unwrap_or_default(),
+                argument_spec.description
+            );
+        }
+    }
+}
@@ -1,33 +1,77 @@
-use std::collections::HashSet;
-use std::f32::INFINITY;
-use std::{ops::Add, time::Duration};
+use std::f32::consts::TAU;
+use std::time::Duration;
 
 use crate::apps::App;
 use crate::clients::client::Client;
-use crate::engine::color::{BLUE, RED, WHITE};
+use crate::clients::input::{Button, InputEvent};
+use crate::engine::color::{BLUE, RED, TRANSPARENT};
+use crate::engine::sound::{play_sound_at, Duty, Envelope, Sound};
 use crate::engine::tile::Tile;
-use crate::engine::world::World;
-use parry2d::math::{Point, Vector};
+use crate::engine::world::{World, MAX_SPRITES};
+use parry2d::math::{Isometry, Point, Vector};
+use parry2d::query;
+use parry2d::shape::Ball as BallShape;
+use rand::Rng;
+
+const BALL_SPEED: f32 = 2.0; // World units/s
+const MAX_BALLS: usize = MAX_SPRITES;
+
+const BOUNCE_SOUND: Sound = Sound::Square {
+    duty: Duty::Half,
+    envelope: Envelope::fade_out(12, 1),
+    frequency: 880.0,
+    length: Some(16),
+};
 
 struct Ball {
     sprite_id: usize,
+    pos: Point<f32>, // Center
     vel: Vector<f32>,
-    // TODO radius?
 }
 
 pub struct BouncingBallsApp {
     world: World,
     balls: Vec<Ball>,
-    known_client_ids: HashSet<u8>,
+    initial_balls: usize,
+
+    // Clients on which a ball was requested since the last update
+    spawn_requests: Vec<u8>,
 }
 
 impl BouncingBallsApp {
     pub fn new() -> Self {
+        Self::with_balls(1)
+    }
+
+    pub fn with_balls(initial_balls: usize) -> Self {
         Self {
             world: World::new(),
             balls: Vec::new(),
-            known_client_ids: HashSet::new(),
+            initial_balls,
+            spawn_requests: Vec::new(),
+        }
+    }
+
+    fn spawn_ball(&mut self, pos: Point<f32>) {
+        if self.balls.len() >= MAX_BALLS {
+            return;
         }
+
+        let sprite_id = match self.world.create_sprite(&BALL_TILE) {
+            Ok(sprite_id) => sprite_id,
+            Err(e) => {
+                println!("Cannot spawn a ball: {e}");
+                return;
+            }
+        };
+
+        let angle = rand::thread_rng().gen_range(0.0..TAU);
+
+        self.balls.push(Ball {
+            sprite_id,
+            pos,
+            vel: Vector::new(angle.cos(), angle.sin()) * BALL_SPEED,
+        });
     }
 }
 
@@ -36,64 +80,146 @@ lazy_static! {
         8,
         8,
         vec![
-            WHITE, WHITE, WHITE, RED,  RED,  WHITE, WHITE, WHITE, //
-            WHITE, WHITE, RED,   BLUE, BLUE, RED,   WHITE, WHITE, //
-            WHITE, RED,   BLUE,  BLUE, BLUE, BLUE,  RED,   WHITE, //
+            TRANSPARENT, TRANSPARENT, TRANSPARENT, RED,  RED,  TRANSPARENT, TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, RED,   BLUE, BLUE, RED,   TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, RED,   BLUE,  BLUE, BLUE, BLUE,  RED,   TRANSPARENT, //
             RED,   BLUE,  BLUE,  BLUE, BLUE, BLUE,  BLUE,  RED, //
             RED,   BLUE,  BLUE,  BLUE, BLUE, BLUE,  BLUE,  RED, //
-            WHITE, RED,   BLUE,  BLUE, BLUE, BLUE,  RED,   WHITE, //
-            WHITE, WHITE, RED,   BLUE, BLUE, RED,   WHITE, WHITE, //
-            WHITE, WHITE, WHITE, RED,  RED,  WHITE, WHITE, WHITE
+            TRANSPARENT, RED,   BLUE,  BLUE, BLUE, BLUE,  RED,   TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, RED,   BLUE, BLUE, RED,   TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, TRANSPARENT, RED,  RED,  TRANSPARENT, TRANSPARENT, TRANSPARENT
             ]
     );
 }
 
+// True if the ball is entirely visible, possibly across several screens
+fn inside_screens(clients: &[Client], center: &Point<f32>, radius: f32) -> bool {
+    [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+        .iter()
+        .all(|(dx, dy)| {
+            let corner = Point::new(center.x + dx * radius, center.y + dy * radius);
+
+            clients
+                .iter()
+                .any(|client| client.screen().contains(&corner))
+        })
+}
+
 impl App for BouncingBallsApp {
     fn update(&mut self, dt: &Duration, clients: &mut Vec<Client>) {
-        let area = self.world.fit_client_screens(clients).clone();
-        // TODO correct ball pos when area changes
+        if clients.is_empty() {
+            return;
+        }
+
+        self.world.fit_client_screens(clients);
+
+        let tile_size = clients[0].screen().tile_size();
+        let radius = tile_size.x.min(tile_size.y) / 2.0;
 
-        // Spawn the first ball
-        // TODO more on input?
+        // Spawn the first balls spread on the screens, then more on input
 
-        if self.balls.is_empty() && area.volume() != INFINITY {
-            self.balls.push(Ball {
-                sprite_id: self.world.create_sprite(&BALL_TILE),
-                vel: Vector::new(0.1, 0.1),
-            });
+        if self.balls.is_empty() {
+            for index in 0..self.initial_balls {
+                let center = clients[index % clients.len()]
+                    .screen()
+                    .bounding_box()
+                    .center();
+                self.spawn_ball(center);
+            }
         }
 
-        // Move the balls
-        // TODO dt
+        for client_id in std::mem::take(&mut self.spawn_requests) {
+            if let Some(client) = clients.iter().find(|client| client.id() == client_id) {
+                let center = client.screen().bounding_box().center();
+                self.spawn_ball(center);
+            }
+        }
 
-        for ball in &mut self.balls {
-            let mut pos = self.world.get_sprite(ball.sprite_id).pos;
+        // Move the balls, bouncing on the edges of the screens
 
-            pos = pos.add(ball.vel);
+        let dt = dt.as_secs_f32();
 
-            // Bounce
-            // TODO play sound on client containing ball
+        for ball in &mut self.balls {
+            let new_pos = ball.pos + ball.vel * dt;
 
-            if pos.x < area.mins.x {
-                pos.x = area.mins.x;
-                ball.vel.x *= -1.0;
+            if inside_screens(clients, &new_pos, radius) {
+                ball.pos = new_pos;
+                continue;
             }
-            if pos.x > area.maxs.x {
-                pos.x = area.maxs.x;
+
+            // Find which axis left the screens to only bounce on this one
+
+            let x_only = Point::new(new_pos.x, ball.pos.y);
+            let y_only = Point::new(ball.pos.x, new_pos.y);
+
+            if inside_screens(clients, &y_only, radius) {
                 ball.vel.x *= -1.0;
-            }
-            if pos.y < area.mins.y {
-                pos.y = area.mins.y;
+                ball.pos = y_only;
+            } else if inside_screens(clients, &x_only, radius) {
                 ball.vel.y *= -1.0;
+                ball.pos = x_only;
+            } else {
+                ball.vel = -ball.vel;
             }
-            if pos.y > area.maxs.y {
-                pos.y = area.maxs.y;
-                ball.vel.y *= -1.0;
+
+            play_sound_at(clients, &ball.pos, &BOUNCE_SOUND);
+
+            // Balls stuck outside of the screens (eg. after moving a screen) go back to one
+
+            if !inside_screens(clients, &ball.pos, radius) {
+                ball.pos = clients[0].screen().bounding_box().center();
+            }
+        }
+
+        // Collide the balls with each other
+
+        let shape = BallShape::new(radius);
+
+        for i in 0..self.balls.len() {
+            for j in (i + 1)..self.balls.len() {
+                let (first, second) = self.balls.split_at_mut(j);
+                let (a, b) = (&mut first[i], &mut second[0]);
+
+                let contact = query::contact(
+                    &Isometry::translation(a.pos.x, a.pos.y),
+                    &shape,
+                    &Isometry::translation(b.pos.x, b.pos.y),
+                    &shape,
+                    0.0,
+                );
+
+                if let Ok(Some(contact)) = contact {
+                    let normal = contact.normal1.into_inner();
+
+                    // Separate the balls
+
+                    let penetration = -contact.dist / 2.0;
+                    a.pos -= normal * penetration;
+                    b.pos += normal * penetration;
+
+                    // Elastic collision between equal masses: exchange the normal velocities
+
+                    let relative_vel = (a.vel - b.vel).dot(&normal);
+
+                    if relative_vel > 0.0 {
+                        a.vel -= normal * relative_vel;
+                        b.vel += normal * relative_vel;
+                    }
+                }
             }
+        }
 
-            self.world.move_sprite(ball.sprite_id, pos.x, pos.y);
+        for ball in &self.balls {
+            self.world
+                .move_sprite(ball.sprite_id, ball.pos.x - radius, ball.pos.y - radius);
         }
 
         self.world.sync_clients(clients);
     }
+
+    fn on_input(&mut self, client_id: u8, event: &InputEvent) {
+        if event.is_press(Button::A) {
+            self.spawn_requests.push(client_id);
+        }
+    }
 }
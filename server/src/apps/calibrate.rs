@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use image::{DynamicImage, Rgb, RgbImage};
+use parry2d::math::{Point, Vector};
+
+use crate::apps::App;
+use crate::clients::client::Client;
+use crate::clients::input::{Button, InputEvent, InputEventKind};
+use crate::clients::screen::{Rotation, Screen};
+use crate::engine::font::{Font, TextImage};
+use crate::engine::slicing::{self, ScreenTiles};
+
+const GRID_SPACING: f32 = 1.0; // World units
+const MAJOR_GRID_LINES: i32 = 5; // Every n lines
+const ID_SCALE: usize = 4;
+
+// Holding a direction keeps moving the screen after a while, at a steady pace
+const REPEAT_DELAY: Duration = Duration::from_millis(500);
+const REPEAT_INTERVAL: Duration = Duration::from_millis(100);
+
+const DIRECTIONS: [Button; 4] = [Button::Left, Button::Right, Button::Up, Button::Down];
+
+// Every screen shows its ID over a grid aligned on the world, that continues across screens
+// once they are in place. Each player moves their own screen:
+//
+// - D-pad: move by one pixel, by one tile while holding B, repeated while held
+// - A: rotate clockwise
+// - Select: mirror
+// - Start + Select: back to unrotated and unmirrored
+pub struct CalibrateApp {
+    font: Font,
+
+    pending_inputs: Vec<(u8, InputEvent)>,
+
+    // Last time each held direction moved a screen, by client and button
+    last_repeats: HashMap<(u8, Button), Instant>,
+
+    // Layout each client was drawn with (position, rotation, mirrored)
+    drawn_layouts: HashMap<u8, (Point<f32>, Rotation, bool)>,
+    screen_tiles: ScreenTiles,
+}
+
+impl CalibrateApp {
+    pub fn new() -> Self {
+        Self {
+            font: Font::builtin(),
+            pending_inputs: Vec::new(),
+            last_repeats: HashMap::new(),
+            drawn_layouts: HashMap::new(),
+            screen_tiles: ScreenTiles::new(),
+        }
+    }
+
+    fn apply_input(client: &mut Client, event: &InputEvent) {
+        if event.completes_combo(&[Button::Start, Button::Select]) {
+            client.set_screen_orientation(Rotation::Deg0, false);
+            return;
+        }
+
+        // Mirror once Select is released, unless it was part of the combo
+        if event.is_release(Button::Select) && !event.held_buttons.contains(Button::Start) {
+            let screen = client.screen();
+            let (rotation, mirrored) = (screen.rotation, !screen.mirrored);
+
+            client.set_screen_orientation(rotation, mirrored);
+            return;
+        }
+
+        if event.kind == InputEventKind::Pressed {
+            CalibrateApp::apply_button(client, event.button);
+        }
+    }
+
+    fn apply_button(client: &mut Client, button: Button) {
+        let screen = client.screen();
+
+        let step = if client.button_pressed(Button::B) {
+            screen.tile_size()
+        } else {
+            screen.tile_size() / 8.0
+        };
+
+        let offset = match button {
+            Button::Left => Vector::new(-step.x, 0.0),
+            Button::Right => Vector::new(step.x, 0.0),
+            Button::Up => Vector::new(0.0, -step.y),
+            Button::Down => Vector::new(0.0, step.y),
+            _ => Vector::zeros(),
+        };
+
+        match button {
+            Button::A => {
+                // Rotate around the center of the screen
+                let center = screen.bounding_box().center();
+                let rotation = screen.rotation.clockwise();
+                let mirrored = screen.mirrored;
+
+                client.set_screen_orientation(rotation, mirrored);
+
+                let half_size = client.screen().world_size() / 2.0;
+                client.set_screen_position(center.x - half_size.x, center.y - half_size.y);
+            }
+            _ if offset != Vector::zeros() => {
+                let pos = screen.pos + offset;
+
+                client.set_screen_position(pos.x, pos.y);
+            }
+            _ => {}
+        }
+    }
+
+    // Grid and labels, oriented as the wall
+    fn render(&self, client_id: u8, screen: &Screen) -> DynamicImage {
+        let world_res = screen.world_res();
+        let pixel_size = screen.tile_size() / 8.0;
+
+        let id_label = self.font.rasterize(&client_id.to_string());
+        let layout_label = self.font.rasterize(&format!(
+            "{:.2} {:.2} {}{}",
+            screen.pos.x,
+            screen.pos.y,
+            screen.rotation.degrees(),
+            if screen.mirrored { " M" } else { "" }
+        ));
+
+        let layout_label_y = id_label.height * ID_SCALE + 12;
+
+        let image = RgbImage::from_fn(world_res.x as u32, world_res.y as u32, |x, y| {
+            let (x, y) = (x as usize, y as usize);
+
+            let label_ink = label_pixel(&id_label, ID_SCALE, x, y, 8, 8)
+                .or_else(|| label_pixel(&layout_label, 1, x, y, 8, layout_label_y));
+
+            let shade = match label_ink {
+                Some(true) => 0x00,
+                Some(false) => 0xFF,
+                None => {
+                    // Lines where the pixel spans a multiple of the grid spacing
+
+                    let x0 = screen.pos.x + x as f32 * pixel_size.x;
+                    let y0 = screen.pos.y + y as f32 * pixel_size.y;
+
+                    let vertical_line = grid_line(x0, x0 + pixel_size.x);
+                    let horizontal_line = grid_line(y0, y0 + pixel_size.y);
+
+                    match vertical_line.max(horizontal_line) {
+                        Some(line) if line % MAJOR_GRID_LINES == 0 => 0x00,
+                        Some(_) => 0x55,
+                        None => 0xFF,
+                    }
+                }
+            };
+
+            Rgb([shade, shade, shade])
+        });
+
+        screen.orient_image(&DynamicImage::ImageRgb8(image))
+    }
+}
+
+// Index of the grid line between the two world coordinates, if any
+fn grid_line(start: f32, end: f32) -> Option<i32> {
+    let line = (end / GRID_SPACING).floor() as i32;
+
+    if line != (start / GRID_SPACING).floor() as i32 {
+        Some(line)
+    } else {
+        None
+    }
+}
+
+// Some(ink) if the pixel is in the label's box, with a 1 pixel margin
+fn label_pixel(
+    label: &TextImage,
+    scale: usize,
+    x: usize,
+    y: usize,
+    label_x: usize,
+    label_y: usize,
+) -> Option<bool> {
+    let in_box = x + 1 >= label_x
+        && y + 1 >= label_y
+        && x < label_x + label.width * scale + 1
+        && y < label_y + label.height * scale + 1;
+
+    if !in_box {
+        return None;
+    }
+
+    if x < label_x || y < label_y {
+        return Some(false);
+    }
+
+    let (label_pixel_x, label_pixel_y) = ((x - label_x) / scale, (y - label_y) / scale);
+
+    if label_pixel_x < label.width && label_pixel_y < label.height {
+        Some(label.pixel(label_pixel_x, label_pixel_y))
+    } else {
+        Some(false)
+    }
+}
+
+impl App for CalibrateApp {
+    fn update(&mut self, _dt: &Duration, clients: &mut Vec<Client>) {
+        for (client_id, event) in std::mem::take(&mut self.pending_inputs) {
+            if let Some(client) = clients.iter_mut().find(|client| client.id() == client_id) {
+                CalibrateApp::apply_input(client, &event);
+            }
+
+            if DIRECTIONS.contains(&event.button) {
+                match event.kind {
+                    InputEventKind::Pressed => {
+                        self.last_repeats
+                            .insert((client_id, event.button), event.time);
+                    }
+                    InputEventKind::Released { .. } => {
+                        self.last_repeats.remove(&(client_id, event.button));
+                    }
+                }
+            }
+        }
+
+        let now = Instant::now();
+
+        for client in clients.iter_mut() {
+            for button in DIRECTIONS {
+                let held_duration = client.input().held_duration(button);
+
+                if held_duration.is_some_and(|held_duration| held_duration >= REPEAT_DELAY) {
+                    let last_repeat = self
+                        .last_repeats
+                        .entry((client.id(), button))
+                        .or_insert(now);
+
+                    if now.duration_since(*last_repeat) >= REPEAT_INTERVAL {
+                        CalibrateApp::apply_button(client, button);
+                        *last_repeat = now;
+                    }
+                }
+            }
+        }
+
+        // Redraw the screens that moved, the others still line up unless they show approximate
+        // tiles
+
+        for client in clients.iter_mut() {
+            let screen = client.screen();
+            let layout = (screen.pos, screen.rotation, screen.mirrored);
+
+            if self.drawn_layouts.get(&client.id()) != Some(&layout)
+                || client.has_approximate_tiles()
+            {
+                let image = self.render(client.id(), screen);
+                let (columns, tiles) = slicing::image_to_tiles(&image);
+
+                self.screen_tiles.draw(client, columns, tiles);
+                self.drawn_layouts.insert(client.id(), layout);
+            }
+        }
+    }
+
+    // Draw again if the client comes back, its screen was reset
+    fn on_client_left(&mut self, client_id: u8) {
+        self.drawn_layouts.remove(&client_id);
+        self.last_repeats.retain(|(id, _), _| *id != client_id);
+        self.screen_tiles.remove(client_id);
+    }
+
+    fn on_input(&mut self, client_id: u8, event: &InputEvent) {
+        self.pending_inputs.push((client_id, *event));
+    }
+}
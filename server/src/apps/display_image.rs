@@ -3,27 +3,37 @@ use std::time::Duration;
 
 use crate::apps::App;
 use crate::clients::client::Client;
+use crate::engine::dithering::Conversion;
+use crate::engine::slicing;
+use crate::ServerCommand;
 use image::imageops::FilterType;
 use image::{DynamicImage, GenericImageView};
-use parry2d::bounding_volume::{BoundingVolume, AABB};
+use parry2d::bounding_volume::AABB;
 
 pub struct DisplayImageApp {
     area: AABB,
     image: DynamicImage,
     known_client_ids: HashSet<u8>,
+    conversion: Conversion,
 }
 
 impl DisplayImageApp {
-    pub fn new() -> Self {
-        let image = image::open("test.png").unwrap();
+    pub fn new(path: &str) -> Result<Self, String> {
+        let image = image::open(path).map_err(|e| format!("Cannot load image {path}: {e}"))?;
 
         println!("Loaded image {:?}", image.dimensions());
 
-        Self {
+        Ok(Self {
             area: AABB::new_invalid(),
             image,
             known_client_ids: HashSet::new(),
-        }
+            conversion: Conversion::default(),
+        })
+    }
+
+    // Until another one is chosen on the console
+    pub fn set_conversion(&mut self, conversion: Conversion) {
+        self.conversion = conversion;
     }
 }
 
@@ -31,11 +41,7 @@ impl App for DisplayImageApp {
     fn update(&mut self, _dt: &Duration, clients: &mut Vec<Client>) {
         // Compute the current bounding box
 
-        let mut new_aabb = AABB::new_invalid();
-
-        for client in clients.iter_mut() {
-            new_aabb.merge(&client.screen().bounding_box());
-        }
+        let new_aabb = slicing::wall_area(clients);
 
         if new_aabb != self.area {
             self.area = new_aabb;
@@ -49,64 +55,8 @@ impl App for DisplayImageApp {
             if !self.known_client_ids.contains(&client.id()) {
                 self.known_client_ids.insert(client.id());
 
-                //
-
-                let screen_x_normalized =
-                    (client.screen().pos.x - self.area.mins.x) / self.area.extents().x;
-                let screen_y_normalized =
-                    (client.screen().pos.y - self.area.mins.y) / self.area.extents().y;
-
-                println!(
-                    "{screen_y_normalized} {} {} {}",
-                    client.screen().pos.y,
-                    self.area.mins.y,
-                    self.area.extents().y
-                );
-
-                let screen_w_normalized = client.screen().size.x / self.area.extents().x;
-                let screen_h_normalized = client.screen().size.y / self.area.extents().y;
-
-                let area_ratio = self.area.extents().x / self.area.extents().y;
-                let image_ratio =
-                    self.image.dimensions().0 as f32 / self.image.dimensions().1 as f32;
-                let relative_ratio = area_ratio / image_ratio;
-
-                println!(
-                    "area {}, image {}, rel {}",
-                    area_ratio, image_ratio, relative_ratio
-                );
-
-                let image_x_normalized = if area_ratio > image_ratio {
-                    screen_x_normalized
-                } else {
-                    screen_x_normalized * relative_ratio
-                };
-
-                let image_y_normalized = if area_ratio > image_ratio {
-                    screen_y_normalized * relative_ratio
-                } else {
-                    screen_y_normalized
-                };
-
-                let image_w_normalized = if area_ratio > image_ratio {
-                    screen_w_normalized
-                } else {
-                    screen_w_normalized * relative_ratio
-                };
-
-                let image_h_normalized = if area_ratio > image_ratio {
-                    screen_h_normalized * relative_ratio
-                } else {
-                    screen_h_normalized
-                };
-
-                println!("{image_y_normalized} {screen_y_normalized} {relative_ratio}");
-                //
-
-                let image_x = (image_x_normalized * self.image.dimensions().0 as f32) as u32;
-                let image_y = (image_y_normalized * self.image.dimensions().1 as f32) as u32;
-                let image_w = (image_w_normalized * self.image.dimensions().0 as f32) as u32;
-                let image_h = (image_h_normalized * self.image.dimensions().1 as f32) as u32;
+                let (image_x, image_y, image_w, image_h) =
+                    slicing::screen_crop(&self.area, client.screen(), self.image.dimensions());
 
                 let cropped_image = self.image.crop(image_x, image_y, image_w, image_h);
 
@@ -120,10 +70,23 @@ impl App for DisplayImageApp {
                     image_h
                 );
 
-                client.fill_screen_with_image(&cropped_image);
+                client.fill_screen_with_image(&cropped_image, &self.conversion);
             }
         }
     }
+
+    // Draw again if the client comes back, its screen was reset
+    fn on_client_left(&mut self, client_id: u8) {
+        self.known_client_ids.remove(&client_id);
+    }
+
+    fn process_server_command(&mut self, command: &ServerCommand) {
+        // Redraw everything with the new conversion
+        if let Some(conversion) = Conversion::from_command(command) {
+            self.conversion = conversion;
+            self.known_client_ids.clear();
+        }
+    }
 }
 
 fn fill_screen_with_image(client: &mut Client, image: &DynamicImage) {
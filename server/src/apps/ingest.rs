@@ -0,0 +1,355 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use image::{DynamicImage, GrayImage, RgbImage};
+use parry2d::bounding_volume::AABB;
+
+use crate::apps::App;
+use crate::clients::client::Client;
+use crate::engine::dithering::Conversion;
+use crate::engine::slicing::{self, ScreenTiles};
+use crate::{FrameArgs, IngestCommand, PixelFormat, ServerCommand};
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:3334";
+
+// How long to wait for a program to open the other end of a named pipe
+const PIPE_OPEN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Shared with the reading thread
+struct IngestState {
+    running: AtomicBool,
+    frame_size: Mutex<Option<(u32, u32)>>,
+    latest_frame: Mutex<Option<DynamicImage>>,
+}
+
+// Display raw frames written by external programs, eg.
+//
+// ffmpeg -re -i video.mp4 -vf scale=W:H -pix_fmt gray -f rawvideo tcp://127.0.0.1:3334
+//
+// Frames have the wall's total resolution unless given otherwise. The app listens on the
+// default address while it runs, unless given another source.
+pub struct IngestApp {
+    state: Arc<IngestState>,
+    fixed_frame_size: Option<(u32, u32)>,
+
+    area: AABB,
+    screen_tiles: ScreenTiles,
+    conversion: Conversion,
+}
+
+impl IngestApp {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(IngestState::new()),
+            fixed_frame_size: None,
+            area: AABB::new_invalid(),
+            screen_tiles: ScreenTiles::new(),
+            conversion: Conversion::default(),
+        }
+    }
+
+    fn stop(&mut self) {
+        self.state.running.store(false, Ordering::Relaxed);
+        self.state = Arc::new(IngestState::new());
+    }
+
+    fn listen(&mut self, address: &str, format: PixelFormat) {
+        self.stop();
+
+        let state = self.state.clone();
+        let address = String::from(address);
+
+        thread::spawn(move || {
+            let listener = match bind(&address) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    println!("Cannot listen on {}: {}", address, e);
+                    return;
+                }
+            };
+
+            println!("ingest: listening on {}", address);
+
+            while state.running.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((mut stream, peer_address)) => {
+                        println!("ingest: new source {}", peer_address);
+
+                        // Timeouts to notice when the app stops
+                        stream.set_nonblocking(false).unwrap();
+                        stream
+                            .set_read_timeout(Some(Duration::from_millis(100)))
+                            .unwrap();
+
+                        read_frames(&mut stream, format, &state);
+
+                        println!("ingest: source {} closed", peer_address);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(100));
+                    }
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    // Read from a file or named pipe (mkfifo) until its end
+    fn read_pipe(&mut self, path: &str, format: PixelFormat) {
+        self.stop();
+
+        let state = self.state.clone();
+        let path = String::from(path);
+
+        thread::spawn(move || {
+            // Opening a named pipe blocks until a writer opens it, wait for it on another
+            // thread to give up after a while. That thread ends once a writer shows up.
+
+            let (sender, receiver) = mpsc::channel();
+
+            let opened_path = path.clone();
+            thread::spawn(move || {
+                let _ = sender.send(File::open(opened_path));
+            });
+
+            let mut waited = Duration::ZERO;
+
+            let opened_file = loop {
+                match receiver.recv_timeout(Duration::from_millis(100)) {
+                    Ok(opened_file) => break opened_file,
+                    Err(RecvTimeoutError::Timeout) => {
+                        waited += Duration::from_millis(100);
+
+                        if !state.running.load(Ordering::Relaxed) {
+                            return;
+                        }
+
+                        if waited >= PIPE_OPEN_TIMEOUT {
+                            println!("ingest: nothing is writing to {}", path);
+                            return;
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            };
+
+            match opened_file {
+                Ok(mut file) => {
+                    println!("ingest: reading {}", path);
+                    read_frames(&mut file, format, &state);
+                    println!("ingest: end of {}", path);
+                }
+                Err(e) => println!("Cannot open {}: {}", path, e),
+            }
+        });
+    }
+
+    fn set_frame_size(&mut self, frame: &FrameArgs) {
+        self.fixed_frame_size = match (frame.width, frame.height) {
+            (Some(width), Some(height)) => Some((width, height)),
+            _ => None,
+        };
+    }
+}
+
+impl Drop for IngestApp {
+    fn drop(&mut self) {
+        self.state.running.store(false, Ordering::Relaxed);
+    }
+}
+
+// The previous listener may still be closing, eg. when restarting the app
+fn bind(address: &str) -> io::Result<TcpListener> {
+    let mut attempts = 0;
+
+    loop {
+        match TcpListener::bind(address) {
+            Ok(listener) => {
+                listener.set_nonblocking(true)?;
+                return Ok(listener);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && attempts < 10 => {
+                attempts += 1;
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl IngestState {
+    fn new() -> Self {
+        Self {
+            running: AtomicBool::new(true),
+            frame_size: Mutex::new(None),
+            latest_frame: Mutex::new(None),
+        }
+    }
+}
+
+// Read frames until the source closes, only keeping the latest one
+fn read_frames(source: &mut impl Read, format: PixelFormat, state: &IngestState) {
+    let mut buffer = Vec::new();
+
+    while state.running.load(Ordering::Relaxed) {
+        let (width, height) = match *state.frame_size.lock().unwrap() {
+            Some(frame_size) => frame_size,
+            None => {
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+
+        buffer.resize(
+            width as usize * height as usize * format.bytes_per_pixel(),
+            0,
+        );
+
+        match read_frame(source, &mut buffer, state) {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            }
+        }
+
+        let frame = match format {
+            PixelFormat::Rgb => {
+                RgbImage::from_raw(width, height, buffer.clone()).map(DynamicImage::ImageRgb8)
+            }
+            PixelFormat::Gray => {
+                GrayImage::from_raw(width, height, buffer.clone()).map(DynamicImage::ImageLuma8)
+            }
+        };
+
+        *state.latest_frame.lock().unwrap() = frame;
+    }
+}
+
+// Fill the buffer, false at the end of the source
+fn read_frame(source: &mut impl Read, buffer: &mut [u8], state: &IngestState) -> io::Result<bool> {
+    let mut length = 0;
+
+    while length < buffer.len() {
+        match source.read(&mut buffer[length..]) {
+            Ok(0) => return Ok(false),
+            Ok(read_length) => length += read_length,
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                if !state.running.load(Ordering::Relaxed) {
+                    return Ok(false);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(true)
+}
+
+impl PixelFormat {
+    fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgb => 3,
+            PixelFormat::Gray => 1,
+        }
+    }
+}
+
+impl App for IngestApp {
+    fn on_start(&mut self, _clients: &mut Vec<Client>) {
+        self.listen(DEFAULT_ADDRESS, PixelFormat::Rgb);
+    }
+
+    // Release the port or pipe for the next app
+    fn on_stop(&mut self, _clients: &mut Vec<Client>) {
+        self.stop();
+    }
+
+    fn update(&mut self, _dt: &Duration, clients: &mut Vec<Client>) {
+        if clients.is_empty() {
+            return;
+        }
+
+        let area = slicing::wall_area(clients);
+
+        // The wall's resolution, at the pixel density of the first screen
+
+        let frame_size = self.fixed_frame_size.unwrap_or_else(|| {
+            let tile_size = clients[0].screen().tile_size();
+
+            (
+                (area.extents().x * 8.0 / tile_size.x).round() as u32,
+                (area.extents().y * 8.0 / tile_size.y).round() as u32,
+            )
+        });
+
+        {
+            let mut current_frame_size = self.state.frame_size.lock().unwrap();
+
+            if *current_frame_size != Some(frame_size) {
+                println!("ingest: expecting {}x{} frames", frame_size.0, frame_size.1);
+                *current_frame_size = Some(frame_size);
+            }
+        }
+
+        let frame = match self.state.latest_frame.lock().unwrap().take() {
+            Some(frame) => frame,
+            None => return,
+        };
+
+        // New layouts are redrawn entirely
+
+        if area != self.area {
+            self.area = area;
+            self.screen_tiles = ScreenTiles::new();
+        }
+
+        for client in clients.iter_mut() {
+            let image = slicing::screen_image(&frame, &area, client.screen());
+            let (columns, tiles) = slicing::image_to_tiles(&self.conversion.convert_image(&image));
+
+            self.screen_tiles.draw(client, columns, tiles);
+        }
+    }
+
+    // Draw again if the client comes back, its screen was reset
+    fn on_client_left(&mut self, client_id: u8) {
+        self.screen_tiles.remove(client_id);
+    }
+
+    fn process_server_command(&mut self, command: &ServerCommand) {
+        if let Some(conversion) = Conversion::from_command(command) {
+            self.conversion = conversion;
+        }
+
+        if let ServerCommand::Ingest { command } = command {
+            match command {
+                IngestCommand::Listen { address, frame } => {
+                    self.set_frame_size(frame);
+                    self.listen(address, frame.format);
+                }
+                IngestCommand::Pipe { path, frame } => {
+                    self.set_frame_size(frame);
+                    self.read_pipe(path, frame.format);
+                }
+                IngestCommand::Stop => {
+                    println!("ingest: stopped");
+                    self.stop();
+                }
+            }
+        }
+    }
+}
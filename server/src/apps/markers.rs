@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use crate::apps::App;
+use crate::clients::client::Client;
+use crate::engine::markers;
+use crate::engine::slicing::{self, ScreenTiles};
+
+// Show each client's marker, to photograph the wall and detect the layout
+pub struct MarkersApp {
+    screen_tiles: ScreenTiles,
+}
+
+impl MarkersApp {
+    pub fn new() -> Self {
+        Self {
+            screen_tiles: ScreenTiles::new(),
+        }
+    }
+}
+
+impl App for MarkersApp {
+    fn update(&mut self, _dt: &Duration, clients: &mut Vec<Client>) {
+        for client in clients.iter_mut() {
+            if self.screen_tiles.needs_redraw(client) {
+                let image = markers::marker_image(client.id(), client.screen().res);
+                let (columns, tiles) = slicing::image_to_tiles(&image);
+
+                self.screen_tiles.draw(client, columns, tiles);
+            }
+        }
+    }
+
+    // Draw again if the client comes back, its screen was reset
+    fn on_client_left(&mut self, client_id: u8) {
+        self.screen_tiles.remove(client_id);
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::Duration;
+
+use parry2d::math::Point;
+
+use crate::apps::App;
+use crate::clients::client::Client;
+use crate::engine::assets::FileWatcher;
+use crate::engine::color::{BLACK, WHITE};
+use crate::engine::font::{Font, TextImage};
+use crate::engine::tile::Tile;
+use crate::engine::world::World;
+use crate::ServerCommand;
+
+const SPEED: f32 = 8.0; // Cells/s
+const SCALE: usize = 2; // Cells per font pixel
+
+pub struct MarqueeApp {
+    world: World,
+    font: Font,
+
+    text: String,
+    image: TextImage,
+    // File watched for new text
+    text_path: Option<String>,
+    watcher: FileWatcher,
+
+    offset: f32, // In cells
+    drawn_cells: HashMap<Point<i32>, bool>,
+}
+
+impl MarqueeApp {
+    pub fn new() -> Self {
+        let font = Font::builtin();
+        let text = String::from("HELLO WALL");
+        let image = font.rasterize(&text);
+
+        Self {
+            world: World::new(),
+            font,
+            text,
+            image,
+            text_path: None,
+            watcher: FileWatcher::new(),
+            offset: 0.0,
+            drawn_cells: HashMap::new(),
+        }
+    }
+
+    pub fn set_font(&mut self, font: Font) {
+        self.font = font;
+        self.image = self.font.rasterize(&self.text);
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        println!("marquee: {}", text);
+
+        self.text = text.to_string();
+        self.image = self.font.rasterize(text);
+        self.offset = 0.0;
+    }
+
+    // Show the text of the file, and again when it changes
+    fn watch_text_file(&mut self, path: &str) {
+        self.watcher.unwatch_all();
+        self.watcher.watch(path);
+        self.text_path = Some(path.to_string());
+
+        self.read_text_file();
+    }
+
+    fn read_text_file(&mut self) {
+        let path = match &self.text_path {
+            Some(path) => path,
+            None => return,
+        };
+
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                // Newlines would be rendered as unknown characters
+                let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+                self.set_text(&text);
+            }
+            Err(e) => println!("Cannot read {}: {}", path, e),
+        }
+    }
+
+    // Is the cell covered by a text pixel?
+    fn is_ink(&self, column: i32, row: i32) -> bool {
+        if column < 0 || row < 0 {
+            return false;
+        }
+
+        let (x, y) = (column as usize / SCALE, row as usize / SCALE);
+
+        x < self.image.width && y < self.image.height && self.image.pixel(x, y)
+    }
+}
+
+lazy_static! {
+    static ref FILLED_TILE: Tile = Tile::filled(8, 8, BLACK);
+    static ref EMPTY_TILE: Tile = Tile::filled(8, 8, WHITE);
+}
+
+impl App for MarqueeApp {
+    fn update(&mut self, dt: &Duration, clients: &mut Vec<Client>) {
+        if clients.is_empty() {
+            return;
+        }
+
+        if !self.watcher.poll(dt).is_empty() {
+            self.read_text_file();
+        }
+
+        let area = *self.world.fit_client_screens(clients);
+
+        let min_cell = self.world.cell_at(&area.mins);
+        let max_cell = self.world.cell_at(&area.maxs);
+
+        // The text enters on the right of the wall and leaves on the left before looping
+
+        let wall_width = max_cell.x - min_cell.x;
+        let text_width = (self.image.width * SCALE) as i32;
+        let text_height = (self.image.height * SCALE) as i32;
+
+        self.offset = (self.offset + SPEED * dt.as_secs_f32()) % (wall_width + text_width) as f32;
+
+        let text_x = max_cell.x - self.offset as i32;
+        let text_y = (min_cell.y + max_cell.y - text_height) / 2;
+
+        // Only send the cells that changed
+
+        for y in min_cell.y..max_cell.y {
+            for x in min_cell.x..max_cell.x {
+                let cell = Point::new(x, y);
+                let ink = self.is_ink(x - text_x, y - text_y);
+
+                if self.drawn_cells.get(&cell) != Some(&ink) {
+                    let tile: &Tile = if ink { &FILLED_TILE } else { &EMPTY_TILE };
+
+                    self.world.set_background_tile(cell, tile);
+                    self.drawn_cells.insert(cell, ink);
+                }
+            }
+        }
+
+        self.world.sync_clients(clients);
+    }
+
+    fn process_server_command(&mut self, command: &ServerCommand) {
+        if let ServerCommand::Marquee { text, file } = command {
+            if !text.is_empty() {
+                self.watcher.unwatch_all();
+                self.text_path = None;
+                self.set_text(&text.join(" "));
+            }
+
+            if let Some(path) = file {
+                self.watch_text_file(path);
+            }
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::HashSet;
+use std::f32::consts::FRAC_PI_4;
+use std::time::Duration;
+
+use crate::apps::App;
+use crate::clients::client::Client;
+use crate::clients::input::Button;
+use crate::engine::color::{BLACK, TRANSPARENT};
+use crate::engine::tile::Tile;
+use crate::engine::world::World;
+use parry2d::bounding_volume::AABB;
+use parry2d::math::{Point, Vector};
+
+const PADDLE_TILES: usize = 3;
+const PADDLE_SPEED: f32 = 4.0; // World units/s
+const BALL_START_SPEED: f32 = 3.0;
+const BALL_SPEEDUP: f32 = 1.1;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Side {
+    Left,
+    Right,
+}
+
+struct Paddle {
+    sprite_ids: Vec<usize>,
+    pos: Point<f32>,
+    score: u32,
+}
+
+struct Ball {
+    sprite_id: usize,
+    vel: Vector<f32>,
+}
+
+pub struct PongApp {
+    world: World,
+    paddles: Option<[Paddle; 2]>,
+    ball: Option<Ball>,
+    serve_to: Side,
+
+    // Clients that display the score, redrawn when it changes
+    scored_client_ids: HashSet<u8>,
+}
+
+impl PongApp {
+    pub fn new() -> Self {
+        Self {
+            world: World::new(),
+            paddles: None,
+            ball: None,
+            serve_to: Side::Left,
+            scored_client_ids: HashSet::new(),
+        }
+    }
+
+    // The paddles and the ball are far from the sprites limit
+    fn create_paddle(&mut self, x: f32, y: f32) -> Paddle {
+        Paddle {
+            sprite_ids: (0..PADDLE_TILES)
+                .map(|_| self.world.create_sprite(&PADDLE_TILE).unwrap())
+                .collect(),
+            pos: Point::new(x, y),
+            score: 0,
+        }
+    }
+
+    fn serve(&mut self, area: &AABB) {
+        let ball = self.ball.as_mut().unwrap();
+
+        let center = area.center();
+        self.world.move_sprite(ball.sprite_id, center.x, center.y);
+
+        let direction = if self.serve_to == Side::Left {
+            -1.0
+        } else {
+            1.0
+        };
+
+        ball.vel = Vector::new(
+            direction * BALL_START_SPEED * FRAC_PI_4.cos(),
+            BALL_START_SPEED * FRAC_PI_4.sin(),
+        );
+    }
+
+    fn move_paddle(&mut self, side: Side, client: &Client, dy: f32, tile_size: &Vector<f32>) {
+        let paddle = &mut self.paddles.as_mut().unwrap()[side as usize];
+
+        // Paddles stay on the screen of the player controlling them
+
+        let screen_box = client.screen().bounding_box();
+        let paddle_height = tile_size.y * PADDLE_TILES as f32;
+
+        paddle.pos.y = (paddle.pos.y + dy).clamp(
+            screen_box.mins.y,
+            (screen_box.maxs.y - paddle_height).max(screen_box.mins.y),
+        );
+
+        for (i, sprite_id) in paddle.sprite_ids.iter().enumerate() {
+            self.world.move_sprite(
+                *sprite_id,
+                paddle.pos.x,
+                paddle.pos.y + i as f32 * tile_size.y,
+            );
+        }
+    }
+
+    fn draw_score(&mut self, clients: &mut [Client], force: bool) {
+        let paddles = self.paddles.as_ref().unwrap();
+        let text = format!("{} - {}", paddles[0].score, paddles[1].score);
+
+        for client in clients.iter_mut() {
+            if force || !self.scored_client_ids.contains(&client.id()) {
+                client.draw_text(&text, 64, 0);
+                self.scored_client_ids.insert(client.id());
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref PADDLE_TILE: Tile = Tile::from_pixels(
+        8,
+        8,
+        vec![
+            TRANSPARENT, TRANSPARENT, BLACK, BLACK, BLACK, BLACK, TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, BLACK, BLACK, BLACK, BLACK, TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, BLACK, BLACK, BLACK, BLACK, TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, BLACK, BLACK, BLACK, BLACK, TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, BLACK, BLACK, BLACK, BLACK, TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, BLACK, BLACK, BLACK, BLACK, TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, BLACK, BLACK, BLACK, BLACK, TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, BLACK, BLACK, BLACK, BLACK, TRANSPARENT, TRANSPARENT
+        ]
+    );
+    static ref BALL_TILE: Tile = Tile::from_pixels(
+        8,
+        8,
+        vec![
+            TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, BLACK, BLACK, BLACK, BLACK, TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, BLACK, BLACK, BLACK, BLACK, TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, BLACK, BLACK, BLACK, BLACK, TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, BLACK, BLACK, BLACK, BLACK, TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, //
+            TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT
+        ]
+    );
+}
+
+impl App for PongApp {
+    fn update(&mut self, dt: &Duration, clients: &mut Vec<Client>) {
+        if clients.is_empty() {
+            return;
+        }
+
+        let area = *self.world.fit_client_screens(clients);
+
+        // World size of a tile, assuming all the screens have the same pixel density
+
+        let tile_size = clients[0].screen().tile_size();
+
+        // The leftmost and rightmost screens control the paddles
+
+        let left_index = (0..clients.len())
+            .min_by(|a, b| {
+                let pos_a = clients[*a].screen().pos.x;
+                let pos_b = clients[*b].screen().pos.x;
+                pos_a.total_cmp(&pos_b)
+            })
+            .unwrap();
+
+        let right_index = (0..clients.len())
+            .max_by(|a, b| {
+                let pos_a = clients[*a].screen().pos.x;
+                let pos_b = clients[*b].screen().pos.x;
+                pos_a.total_cmp(&pos_b)
+            })
+            .unwrap();
+
+        // Setup the game on the first update
+
+        if self.paddles.is_none() {
+            let center_y = area.center().y - tile_size.y * PADDLE_TILES as f32 / 2.0;
+
+            self.paddles = Some([
+                self.create_paddle(area.mins.x, center_y),
+                self.create_paddle(area.maxs.x - tile_size.x, center_y),
+            ]);
+
+            self.ball = Some(Ball {
+                sprite_id: self.world.create_sprite(&BALL_TILE).unwrap(),
+                vel: Vector::zeros(),
+            });
+
+            self.serve(&area);
+        }
+
+        // Keep the paddles on the edges of the wall, in case screens moved
+
+        {
+            let paddles = self.paddles.as_mut().unwrap();
+            paddles[0].pos.x = area.mins.x;
+            paddles[1].pos.x = area.maxs.x - tile_size.x;
+        }
+
+        // Move the paddles
+        // (a single screen controls both paddles, the right one with A/B)
+
+        let dt = dt.as_secs_f32();
+
+        let direction = |client: &Client, up: Button, down: Button| {
+            let mut direction = 0.0;
+            if client.button_pressed(up) {
+                direction -= 1.0;
+            }
+            if client.button_pressed(down) {
+                direction += 1.0;
+            }
+            direction * PADDLE_SPEED * dt
+        };
+
+        let left_dy = direction(&clients[left_index], Button::Up, Button::Down);
+
+        let right_dy = if left_index == right_index {
+            direction(&clients[right_index], Button::A, Button::B)
+        } else {
+            direction(&clients[right_index], Button::Up, Button::Down)
+        };
+
+        self.move_paddle(Side::Left, &clients[left_index], left_dy, &tile_size);
+        self.move_paddle(Side::Right, &clients[right_index], right_dy, &tile_size);
+
+        // Move the ball
+
+        let ball = self.ball.as_mut().unwrap();
+        let mut pos = self.world.get_sprite(ball.sprite_id).pos + ball.vel * dt;
+
+        // Bounce on the top and bottom of the wall
+
+        if pos.y < area.mins.y {
+            pos.y = area.mins.y;
+            ball.vel.y = ball.vel.y.abs();
+        }
+        if pos.y + tile_size.y > area.maxs.y {
+            pos.y = area.maxs.y - tile_size.y;
+            ball.vel.y = -ball.vel.y.abs();
+        }
+
+        // Bounce on the paddles
+
+        let paddles = self.paddles.as_mut().unwrap();
+        let paddle_height = tile_size.y * PADDLE_TILES as f32;
+
+        let hits = |paddle: &Paddle, pos: &Point<f32>| {
+            pos.y + tile_size.y > paddle.pos.y && pos.y < paddle.pos.y + paddle_height
+        };
+
+        let mut scorer = None;
+
+        if pos.x < paddles[0].pos.x + tile_size.x && ball.vel.x < 0.0 {
+            if hits(&paddles[0], &pos) {
+                pos.x = paddles[0].pos.x + tile_size.x;
+                ball.vel.x = -ball.vel.x * BALL_SPEEDUP;
+            } else {
+                scorer = Some(Side::Right);
+            }
+        }
+
+        if pos.x + tile_size.x > paddles[1].pos.x && ball.vel.x > 0.0 {
+            if hits(&paddles[1], &pos) {
+                pos.x = paddles[1].pos.x - tile_size.x;
+                ball.vel.x = -ball.vel.x * BALL_SPEEDUP;
+            } else {
+                scorer = Some(Side::Left);
+            }
+        }
+
+        let ball_sprite_id = ball.sprite_id;
+
+        match scorer {
+            Some(side) => {
+                paddles[side as usize].score += 1;
+
+                // The player who lost the point receives the next serve
+                self.serve_to = if side == Side::Left {
+                    Side::Right
+                } else {
+                    Side::Left
+                };
+
+                self.serve(&area);
+                self.draw_score(clients, true);
+            }
+            None => {
+                self.world.move_sprite(ball_sprite_id, pos.x, pos.y);
+                self.draw_score(clients, false);
+            }
+        }
+
+        self.world.sync_clients(clients);
+    }
+
+    // Draw the score again if the client comes back, its screen was reset
+    fn on_client_left(&mut self, client_id: u8) {
+        self.scored_client_ids.remove(&client_id);
+    }
+}
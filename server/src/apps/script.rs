@@ -0,0 +1,680 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
+
+use parry2d::bounding_volume::AABB;
+use parry2d::math::Point;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST};
+
+use crate::apps::App;
+use crate::clients::client::Client;
+use crate::clients::input::{Button, InputEvent, InputEventKind};
+use crate::engine::assets::{Animation, FileWatcher, SpriteSheet, WatchedTileset};
+use crate::engine::color::{Color, TRANSPARENT};
+use crate::engine::dithering::SHADES;
+use crate::engine::font;
+use crate::engine::tile::Tile;
+use crate::engine::tiled::{Properties, TiledMap};
+use crate::engine::world::World;
+
+// What the scripts see of the server, shared with the functions they call
+struct ScriptState {
+    world: World,
+    tiles: Vec<Tile>,
+    // Reloaded when their file changes, with the IDs of their tiles
+    tilesets: Vec<(WatchedTileset, Range<usize>)>,
+    // Tile IDs of each frame
+    animations: Vec<(Animation, Vec<Array>)>,
+
+    // Updated before running the script
+    wall: AABB,
+    screens: Vec<(u8, AABB)>,
+    held_buttons: HashMap<u8, Vec<Button>>,
+    time: Duration,
+}
+
+impl ScriptState {
+    fn new() -> Self {
+        Self {
+            world: World::new(),
+            tiles: Vec::new(),
+            tilesets: Vec::new(),
+            animations: Vec::new(),
+            wall: AABB::new_invalid(),
+            screens: Vec::new(),
+            held_buttons: HashMap::new(),
+            time: Duration::ZERO,
+        }
+    }
+
+    // The changed tiles are replaced in the world too
+    fn reload_tilesets(&mut self, dt: &Duration) {
+        for (tileset, tile_ids) in self.tilesets.iter_mut() {
+            let changed_tiles = tileset.reload_if_changed(dt);
+
+            if changed_tiles.is_empty() {
+                continue;
+            }
+
+            for (old_tile, new_tile) in changed_tiles {
+                self.world.replace_tile(&old_tile, &new_tile);
+            }
+
+            for (tile_id, tile) in tile_ids.clone().zip(&tileset.tiles) {
+                self.tiles[tile_id] = tile.clone();
+            }
+        }
+    }
+
+    fn tile(&self, tile_id: i64) -> Result<&Tile, Box<EvalAltResult>> {
+        self.tiles
+            .get(tile_id as usize)
+            .ok_or_else(|| format!("no tile {tile_id}").into())
+    }
+
+    fn sprite_id(&self, id: i64) -> Result<usize, Box<EvalAltResult>> {
+        usize::try_from(id)
+            .ok()
+            .filter(|id| self.world.has_sprite(*id))
+            .ok_or_else(|| format!("no sprite {id}").into())
+    }
+
+    fn text_id(&self, id: i64) -> Result<usize, Box<EvalAltResult>> {
+        usize::try_from(id)
+            .ok()
+            .filter(|id| self.world.has_text(*id))
+            .ok_or_else(|| format!("no text {id}").into())
+    }
+}
+
+// App written in Rhai (https://rhai.rs), reloaded when its file is saved. The script can
+// define these functions, with `this` being a map kept between the calls for its own state:
+//
+// fn init()                       once the first clients are connected
+// fn update(dt)                   every update, dt in seconds
+// fn on_press(client_id, button)  button is "A", "B", "Start", "Select", "Up", "Down", ...
+// fn on_release(client_id, button)
+//
+// The world is in background cells and sprites, positions in world units:
+//
+// tile(["0123....", ...])    8x8 tile from rows of shades (0 white - 3 black, . transparent),
+//                            returns its ID
+// load_tiles(path)           IDs of the 8x8 tiles of a spritesheet, reloaded when it changes
+// load_animation(path, tag)  Aseprite animation, "" if the sheet has no tags, returns
+//                            #{id, columns, rows} of its frames in 8x8 tiles
+// animation_tiles(id, time)  tile IDs of the frame at the time in seconds, row by row
+// load_map(path)             Tiled map drawn on the background from the origin, returns
+//                            #{columns, rows, properties, layers: [#{name, properties}],
+//                            objects: [#{name, kind, layer, x, y, width, height,
+//                            properties, sprites}]}, the sprites of tile objects row by row
+// set_background(column, row, tile), cell_size() -> #{x, y}
+// sprite(tile) -> ID, move_sprite(id, x, y), set_sprite_tile(id, tile), delete_sprite(id),
+// 40 sprites at most
+// text(string) -> ID, set_text(id, string), move_text(id, x, y), delete_text(id)
+// wall() -> #{x, y, width, height}, screens() -> [#{id, x, y, width, height}]
+// is_held(client_id, button), time() in seconds, random() between 0 and 1
+pub struct ScriptApp {
+    path: String,
+    watcher: FileWatcher,
+
+    engine: Engine,
+    ast: Option<AST>,
+    scope: Scope<'static>,
+    this: Dynamic,
+    initialized: bool,
+
+    state: Rc<RefCell<ScriptState>>,
+    reload_requested: bool,
+}
+
+impl ScriptApp {
+    pub fn new(path: &str) -> Self {
+        let state = Rc::new(RefCell::new(ScriptState::new()));
+        let mut watcher = FileWatcher::new();
+        watcher.watch(path);
+
+        Self {
+            path: path.to_string(),
+            watcher,
+            engine: script_engine(&state),
+            ast: None,
+            scope: Scope::new(),
+            this: Dynamic::from(Map::new()),
+            initialized: false,
+            state,
+            reload_requested: true,
+        }
+    }
+
+    // Start over with an empty world, the script's top-level statements are run again
+    fn reload(&mut self, clients: &mut Vec<Client>) {
+        {
+            let mut state = self.state.borrow_mut();
+
+            state.world.clear();
+            state.world.sync_clients(clients);
+
+            *state = ScriptState::new();
+        }
+
+        self.ast = None;
+        self.scope = Scope::new();
+        self.this = Dynamic::from(Map::new());
+        self.initialized = false;
+
+        let path = &self.path;
+
+        let ast = match self.engine.compile_file(PathBuf::from(path)) {
+            Ok(ast) => ast,
+            Err(e) => {
+                println!("Cannot load script {}: {}", path, e);
+                return;
+            }
+        };
+
+        println!("loaded script {}", path);
+
+        if let Err(e) = self.engine.run_ast_with_scope(&mut self.scope, &ast) {
+            println!("Script error: {}", e);
+        }
+
+        self.ast = Some(ast);
+    }
+
+    // Scripts don't have to define every function
+    fn call(&mut self, name: &str, args: impl FuncArgs) {
+        let ast = match &self.ast {
+            Some(ast) => ast,
+            None => return,
+        };
+
+        if !ast.iter_functions().any(|function| function.name == name) {
+            return;
+        }
+
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.this);
+
+        if let Err(e) =
+            self.engine
+                .call_fn_with_options::<Dynamic>(options, &mut self.scope, ast, name, args)
+        {
+            println!("Script error in {}: {}", name, e);
+        }
+    }
+}
+
+fn button_name(button: Button) -> String {
+    format!("{:?}", button)
+}
+
+fn parse_button(name: &str) -> Option<Button> {
+    Button::ALL
+        .into_iter()
+        .find(|button| button_name(*button).eq_ignore_ascii_case(name))
+}
+
+// Integers are accepted where floats are expected
+fn number(value: &Dynamic) -> Result<f32, Box<EvalAltResult>> {
+    let number = value
+        .as_float()
+        .map(|value| value as f32)
+        .or_else(|_| value.as_int().map(|value| value as f32))
+        .map_err(|type_name| format!("number expected, got {type_name}"))?;
+
+    // Infinite or NaN positions cannot be drawn
+    if !number.is_finite() {
+        return Err(format!("finite number expected, got {number}").into());
+    }
+
+    Ok(number)
+}
+
+fn cell(column: i64, row: i64) -> Result<Point<i32>, Box<EvalAltResult>> {
+    match (i32::try_from(column), i32::try_from(row)) {
+        (Ok(column), Ok(row)) => Ok(Point::new(column, row)),
+        _ => Err(format!("no cell {column}, {row}").into()),
+    }
+}
+
+fn area_map(area: &AABB) -> Map {
+    let mut map = Map::new();
+
+    map.insert("x".into(), Dynamic::from_float(area.mins.x as f64));
+    map.insert("y".into(), Dynamic::from_float(area.mins.y as f64));
+    map.insert("width".into(), Dynamic::from_float(area.extents().x as f64));
+    map.insert(
+        "height".into(),
+        Dynamic::from_float(area.extents().y as f64),
+    );
+
+    map
+}
+
+// Rows of shade digits, '.' for transparent pixels
+fn parse_tile(rows: &Array) -> Result<Tile, Box<EvalAltResult>> {
+    let rows: Vec<String> = rows
+        .iter()
+        .map(|row| row.clone().into_string())
+        .collect::<Result<_, _>>()
+        .map_err(|type_name| format!("tile rows must be strings, got {type_name}"))?;
+
+    let width = rows.first().map_or(0, |row| row.chars().count());
+
+    // The size of the hardware tiles
+    if width != 8 || rows.len() != 8 {
+        return Err(format!("tiles must be 8x8, got {width}x{}", rows.len()).into());
+    }
+
+    let mut pixels = Vec::with_capacity(width * rows.len());
+
+    for row in &rows {
+        if row.chars().count() != width {
+            return Err("tile rows must have the same length".into());
+        }
+
+        for shade in row.chars() {
+            let color: Color = match shade {
+                '.' => TRANSPARENT,
+                '0'..='3' => SHADES[shade as usize - '0' as usize],
+                _ => return Err(format!("unknown shade {shade}").into()),
+            };
+
+            pixels.push(color);
+        }
+    }
+
+    Ok(Tile::from_pixels(width as u8, rows.len() as u8, pixels))
+}
+
+fn script_engine(state: &Rc<RefCell<ScriptState>>) -> Engine {
+    let mut engine = Engine::new();
+
+    // Tiles
+
+    let s = state.clone();
+    engine.register_fn(
+        "tile",
+        move |rows: Array| -> Result<i64, Box<EvalAltResult>> {
+            let tile = parse_tile(&rows)?;
+            let mut state = s.borrow_mut();
+
+            state.tiles.push(tile);
+            Ok(state.tiles.len() as i64 - 1)
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn(
+        "load_tiles",
+        move |path: &str| -> Result<Array, Box<EvalAltResult>> {
+            let tileset = WatchedTileset::load(path)?;
+            let mut state = s.borrow_mut();
+
+            let tile_ids = state.tiles.len()..state.tiles.len() + tileset.tiles.len();
+            state.tiles.extend(tileset.tiles.iter().cloned());
+            state.tilesets.push((tileset, tile_ids.clone()));
+
+            Ok(tile_ids
+                .map(|tile_id| Dynamic::from(tile_id as i64))
+                .collect())
+        },
+    );
+
+    // Background
+
+    let s = state.clone();
+    engine.register_fn(
+        "set_background",
+        move |column: i64, row: i64, tile_id: i64| -> Result<(), Box<EvalAltResult>> {
+            let mut state = s.borrow_mut();
+            let tile = state.tile(tile_id)?.clone();
+
+            state.world.set_background_tile(cell(column, row)?, &tile);
+            Ok(())
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn("cell_size", move || -> Map {
+        let cell_size = s.borrow().world.background_cell_size();
+        let mut map = Map::new();
+
+        map.insert("x".into(), Dynamic::from_float(cell_size.x as f64));
+        map.insert("y".into(), Dynamic::from_float(cell_size.y as f64));
+        map
+    });
+
+    // Animations
+
+    let s = state.clone();
+    engine.register_fn(
+        "load_animation",
+        move |path: &str, tag: &str| -> Result<Map, Box<EvalAltResult>> {
+            let sprite_sheet = SpriteSheet::load_aseprite(path)?;
+            let animation = sprite_sheet
+                .animation(tag)
+                .ok_or_else(|| format!("{path}: no animation {tag}"))?
+                .clone();
+
+            let mut state = s.borrow_mut();
+            let mut frame_tile_ids = Vec::with_capacity(animation.frames.len());
+
+            for frame in &animation.frames {
+                let first_id = state.tiles.len() as i64;
+                state.tiles.extend(frame.tiles.iter().cloned());
+
+                frame_tile_ids.push(
+                    (first_id..state.tiles.len() as i64)
+                        .map(Dynamic::from)
+                        .collect(),
+                );
+            }
+
+            let (columns, rows) = animation.frames.first().map_or((0, 0), |frame| {
+                (frame.columns, frame.tiles.len() / frame.columns.max(1))
+            });
+
+            let mut map = Map::new();
+            map.insert("id".into(), Dynamic::from(state.animations.len() as i64));
+            map.insert("columns".into(), Dynamic::from(columns as i64));
+            map.insert("rows".into(), Dynamic::from(rows as i64));
+
+            state.animations.push((animation, frame_tile_ids));
+
+            Ok(map)
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn(
+        "animation_tiles",
+        move |id: i64, time: Dynamic| -> Result<Array, Box<EvalAltResult>> {
+            let state = s.borrow();
+            let (animation, frame_tile_ids) = state
+                .animations
+                .get(id as usize)
+                .ok_or_else(|| format!("no animation {id}"))?;
+
+            let time = Duration::try_from_secs_f32(number(&time)?)
+                .map_err(|_| "the time must be positive")?;
+
+            Ok(animation
+                .frame_index_at(time)
+                .map_or_else(Array::new, |index| frame_tile_ids[index].clone()))
+        },
+    );
+
+    // Tiled maps
+
+    let s = state.clone();
+    engine.register_fn(
+        "load_map",
+        move |path: &str| -> Result<Map, Box<EvalAltResult>> {
+            let map = TiledMap::load(path)?;
+            let mut state = s.borrow_mut();
+
+            let objects = map.add_to_world(&mut state.world)?;
+            let cell_size = state.world.background_cell_size();
+
+            let objects: Array = objects
+                .into_iter()
+                .map(|(sprite_ids, object)| {
+                    let pos = map.world_position(&state.world, &object.pos);
+                    let mut object_map = Map::new();
+
+                    object_map.insert("name".into(), object.name.clone().into());
+                    object_map.insert("kind".into(), object.kind.clone().into());
+                    object_map.insert("layer".into(), object.layer.clone().into());
+                    object_map.insert("x".into(), Dynamic::from_float(pos.x as f64));
+                    object_map.insert("y".into(), Dynamic::from_float(pos.y as f64));
+                    object_map.insert(
+                        "width".into(),
+                        Dynamic::from_float((object.size.x / 8.0 * cell_size.x) as f64),
+                    );
+                    object_map.insert(
+                        "height".into(),
+                        Dynamic::from_float((object.size.y / 8.0 * cell_size.y) as f64),
+                    );
+                    object_map.insert(
+                        "properties".into(),
+                        properties_map(&object.properties).into(),
+                    );
+                    object_map.insert(
+                        "sprites".into(),
+                        sprite_ids
+                            .into_iter()
+                            .map(|id| Dynamic::from(id as i64))
+                            .collect::<Array>()
+                            .into(),
+                    );
+
+                    Dynamic::from(object_map)
+                })
+                .collect();
+
+            let layers: Array = map
+                .layers
+                .iter()
+                .map(|layer| {
+                    let mut layer_map = Map::new();
+
+                    layer_map.insert("name".into(), layer.name.clone().into());
+                    layer_map.insert(
+                        "properties".into(),
+                        properties_map(&layer.properties).into(),
+                    );
+
+                    Dynamic::from(layer_map)
+                })
+                .collect();
+
+            let mut result = Map::new();
+            result.insert("columns".into(), Dynamic::from(map.columns as i64));
+            result.insert("rows".into(), Dynamic::from(map.rows as i64));
+            result.insert("properties".into(), properties_map(&map.properties).into());
+            result.insert("layers".into(), layers.into());
+            result.insert("objects".into(), objects.into());
+
+            Ok(result)
+        },
+    );
+
+    // Sprites
+
+    let s = state.clone();
+    engine.register_fn(
+        "sprite",
+        move |tile_id: i64| -> Result<i64, Box<EvalAltResult>> {
+            let mut state = s.borrow_mut();
+            let tile = state.tile(tile_id)?.clone();
+
+            Ok(state.world.create_sprite(&tile)? as i64)
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn(
+        "move_sprite",
+        move |id: i64, x: Dynamic, y: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            let mut state = s.borrow_mut();
+            let id = state.sprite_id(id)?;
+
+            state.world.move_sprite(id, number(&x)?, number(&y)?);
+            Ok(())
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn(
+        "set_sprite_tile",
+        move |id: i64, tile_id: i64| -> Result<(), Box<EvalAltResult>> {
+            let mut state = s.borrow_mut();
+            let id = state.sprite_id(id)?;
+            let tile = state.tile(tile_id)?.clone();
+
+            state.world.set_sprite_tile(id, &tile);
+            Ok(())
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn(
+        "delete_sprite",
+        move |id: i64| -> Result<(), Box<EvalAltResult>> {
+            let mut state = s.borrow_mut();
+            let id = state.sprite_id(id)?;
+
+            state.world.delete_sprite(id);
+            Ok(())
+        },
+    );
+
+    // Texts
+
+    let s = state.clone();
+    engine.register_fn("text", move |text: &str| -> i64 {
+        s.borrow_mut().world.create_text(text, font::default_font()) as i64
+    });
+
+    let s = state.clone();
+    engine.register_fn(
+        "set_text",
+        move |id: i64, text: &str| -> Result<(), Box<EvalAltResult>> {
+            let mut state = s.borrow_mut();
+            let id = state.text_id(id)?;
+
+            state.world.set_text(id, text);
+            Ok(())
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn(
+        "move_text",
+        move |id: i64, x: Dynamic, y: Dynamic| -> Result<(), Box<EvalAltResult>> {
+            let mut state = s.borrow_mut();
+            let id = state.text_id(id)?;
+
+            state.world.move_text(id, number(&x)?, number(&y)?);
+            Ok(())
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn(
+        "delete_text",
+        move |id: i64| -> Result<(), Box<EvalAltResult>> {
+            let mut state = s.borrow_mut();
+            let id = state.text_id(id)?;
+
+            state.world.delete_text(id);
+            Ok(())
+        },
+    );
+
+    // Wall, input and time
+
+    let s = state.clone();
+    engine.register_fn("wall", move || -> Map { area_map(&s.borrow().wall) });
+
+    let s = state.clone();
+    engine.register_fn("screens", move || -> Array {
+        s.borrow()
+            .screens
+            .iter()
+            .map(|(client_id, area)| {
+                let mut map = area_map(area);
+                map.insert("id".into(), Dynamic::from_int(*client_id as i64));
+                Dynamic::from_map(map)
+            })
+            .collect()
+    });
+
+    let s = state.clone();
+    engine.register_fn(
+        "is_held",
+        move |client_id: i64, button: &str| -> Result<bool, Box<EvalAltResult>> {
+            let button = parse_button(button).ok_or(format!("unknown button {button}"))?;
+
+            Ok(s.borrow()
+                .held_buttons
+                .get(&(client_id as u8))
+                .is_some_and(|buttons| buttons.contains(&button)))
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn("time", move || -> f64 { s.borrow().time.as_secs_f64() });
+
+    engine.register_fn("random", || -> f64 { rand::random::<f64>() });
+
+    engine
+}
+
+fn properties_map(properties: &Properties) -> Map {
+    properties
+        .iter()
+        .map(|(name, value)| (name.into(), value.clone().into()))
+        .collect()
+}
+
+impl App for ScriptApp {
+    fn update(&mut self, dt: &Duration, clients: &mut Vec<Client>) {
+        if !self.watcher.poll(dt).is_empty() {
+            self.reload_requested = true;
+        }
+
+        if self.reload_requested {
+            self.reload_requested = false;
+            self.reload(clients);
+        }
+
+        {
+            let mut state = self.state.borrow_mut();
+
+            state.wall = *state.world.fit_client_screens(clients);
+            state.screens = clients
+                .iter()
+                .map(|client| (client.id(), client.screen().bounding_box()))
+                .collect();
+            state.held_buttons = clients
+                .iter()
+                .map(|client| (client.id(), client.input().state().iter().collect()))
+                .collect();
+            state.time += *dt;
+
+            state.reload_tilesets(dt);
+        }
+
+        // The world needs the screens to know its cell size
+
+        if !self.initialized && !clients.is_empty() {
+            self.initialized = true;
+            self.call("init", ());
+        }
+
+        if self.initialized {
+            self.call("update", (dt.as_secs_f64(),));
+        }
+
+        self.state.borrow_mut().world.sync_clients(clients);
+    }
+
+    fn on_input(&mut self, client_id: u8, event: &InputEvent) {
+        if !self.initialized {
+            return;
+        }
+
+        let name = match event.kind {
+            InputEventKind::Pressed => "on_press",
+            InputEventKind::Released { .. } => "on_release",
+        };
+
+        self.call(name, (client_id as i64, button_name(event.button)));
+    }
+}
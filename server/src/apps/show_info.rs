@@ -63,4 +63,9 @@ impl App for ShowInfoApp {
             }
         }
     }
+
+    // Draw again if the client comes back, its screen was reset
+    fn on_client_left(&mut self, client_id: u8) {
+        self.last_client_info.remove(&client_id);
+    }
 }
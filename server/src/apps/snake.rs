@@ -0,0 +1,426 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+use parry2d::bounding_volume::AABB;
+use parry2d::math::{Point, Vector};
+use rand::seq::SliceRandom;
+
+use crate::apps::App;
+use crate::clients::client::Client;
+use crate::clients::input::{Button, InputEvent, InputEventKind};
+use crate::engine::color::{Color, BLACK, DARK_GRAY, WHITE};
+use crate::engine::font;
+use crate::engine::tile::Tile;
+use crate::engine::world::World;
+
+const STEP_DELAY: Duration = Duration::from_millis(150);
+const START_LENGTH: usize = 3;
+const GROWTH_PER_FOOD: usize = 2;
+
+struct Snake {
+    body: VecDeque<Point<i32>>, // Head first
+    direction: Vector<i32>,
+    next_direction: Vector<i32>,
+    growth: usize,
+    alive: bool,
+    respawn: bool,
+    score: u32,
+    score_changed: bool,
+}
+
+pub struct SnakeApp {
+    world: World,
+
+    // Cells of the playfield, in the world background grid
+    arena: AABB,
+    arena_cells: (Point<i32>, Point<i32>),
+    visible_cells: Vec<Point<i32>>,
+
+    // The top row of each screen shows the score instead
+    score_cells: HashSet<Point<i32>>,
+    score_texts: HashMap<u8, usize>,
+
+    snakes: HashMap<u8, Snake>,
+    food: Option<Point<i32>>,
+
+    time_since_last_step: Duration,
+}
+
+impl SnakeApp {
+    pub fn new() -> Self {
+        Self {
+            world: World::new(),
+            arena: AABB::new_invalid(),
+            arena_cells: (Point::origin(), Point::origin()),
+            visible_cells: Vec::new(),
+            score_cells: HashSet::new(),
+            score_texts: HashMap::new(),
+            snakes: HashMap::new(),
+            food: None,
+            time_since_last_step: Duration::ZERO,
+        }
+    }
+
+    // Reset the playfield to fit the screens
+    fn setup_arena(&mut self, clients: &[Client]) {
+        let min_cell = self.world.cell_at(&self.arena.mins);
+        let max_cell = self.world.cell_at(&self.arena.maxs);
+
+        self.arena_cells = (min_cell, max_cell);
+        self.visible_cells.clear();
+        self.score_cells.clear();
+
+        let cell_height = self.world.background_cell_size().y;
+
+        for y in min_cell.y..max_cell.y {
+            for x in min_cell.x..max_cell.x {
+                let cell = Point::new(x, y);
+                let center = self.world.cell_center(&cell);
+
+                let is_score_cell = clients.iter().any(|client| {
+                    client.screen().contains(&center)
+                        && center.y < client.screen().bounding_box().mins.y + cell_height
+                });
+
+                if is_score_cell {
+                    self.score_cells.insert(cell);
+                } else if clients
+                    .iter()
+                    .any(|client| client.screen().contains(&center))
+                {
+                    self.visible_cells.push(cell);
+                }
+
+                self.world.set_background_tile(cell, &EMPTY_TILE);
+            }
+        }
+
+        for client in clients.iter() {
+            if let Some(text_id) = self.score_texts.get(&client.id()) {
+                let top_left = client.screen().bounding_box().mins;
+                self.world.move_text(*text_id, top_left.x, top_left.y);
+            }
+        }
+
+        // Restart every snake since the arena changed under them
+
+        let ids: Vec<u8> = self.snakes.keys().copied().collect();
+        for id in ids {
+            self.spawn_snake(id, clients);
+        }
+
+        self.spawn_food();
+    }
+
+    fn in_arena(&self, cell: &Point<i32>) -> bool {
+        let (min_cell, max_cell) = self.arena_cells;
+
+        cell.x >= min_cell.x
+            && cell.x < max_cell.x
+            && cell.y >= min_cell.y
+            && cell.y < max_cell.y
+            && !self.score_cells.contains(cell)
+    }
+
+    fn is_occupied(&self, cell: &Point<i32>) -> bool {
+        self.snakes
+            .values()
+            .any(|snake| snake.alive && snake.body.contains(cell))
+    }
+
+    // Start the client's snake at the center of its screen
+    fn spawn_snake(&mut self, client_id: u8, clients: &[Client]) {
+        let center = match clients.iter().find(|client| client.id() == client_id) {
+            Some(client) => client.screen().bounding_box().center(),
+            None => return,
+        };
+
+        let score = match self.snakes.remove(&client_id) {
+            Some(old_snake) => {
+                self.clear_snake(&old_snake);
+                old_snake.score
+            }
+            None => 0,
+        };
+
+        let head = self.world.cell_at(&center);
+
+        let body: VecDeque<Point<i32>> = (0..START_LENGTH as i32)
+            .map(|i| Point::new(head.x - i, head.y))
+            .filter(|cell| self.in_arena(cell))
+            .collect();
+
+        for cell in body.iter() {
+            self.world.set_background_tile(*cell, snake_tile(client_id));
+        }
+
+        self.snakes.insert(
+            client_id,
+            Snake {
+                body,
+                direction: Vector::new(1, 0),
+                next_direction: Vector::new(1, 0),
+                growth: 0,
+                alive: true,
+                respawn: false,
+                score,
+                score_changed: true,
+            },
+        );
+    }
+
+    fn clear_snake(&mut self, snake: &Snake) {
+        for cell in snake.body.iter() {
+            self.world.set_background_tile(*cell, &EMPTY_TILE);
+        }
+    }
+
+    fn spawn_food(&mut self) {
+        let free_cells: Vec<Point<i32>> = self
+            .visible_cells
+            .iter()
+            .filter(|cell| !self.is_occupied(cell))
+            .copied()
+            .collect();
+
+        self.food = free_cells.choose(&mut rand::thread_rng()).copied();
+
+        if let Some(food) = self.food {
+            self.world.set_background_tile(food, &FOOD_TILE);
+        }
+    }
+
+    fn step(&mut self) {
+        // Compute the new heads
+
+        let mut new_heads: HashMap<u8, Point<i32>> = HashMap::new();
+
+        for (id, snake) in self
+            .snakes
+            .iter_mut()
+            .filter(|(_, snake)| snake.alive && !snake.body.is_empty())
+        {
+            snake.direction = snake.next_direction;
+
+            let head = snake.body.front().unwrap();
+            new_heads.insert(*id, head + snake.direction);
+        }
+
+        // Check for collisions with the arena bounds, the other snakes and other heads
+        // (tails that are about to move do not block)
+
+        let mut dead_ids = Vec::new();
+
+        for (id, head) in new_heads.iter() {
+            let hits_snake = self
+                .snakes
+                .values()
+                .filter(|snake| snake.alive)
+                .any(|snake| {
+                    let moving_tail = if snake.growth == 0 {
+                        snake.body.back()
+                    } else {
+                        None
+                    };
+
+                    snake.body.contains(head) && moving_tail != Some(head)
+                });
+
+            let hits_head = new_heads
+                .iter()
+                .any(|(other_id, other_head)| other_id != id && other_head == head);
+
+            if !self.in_arena(head) || hits_snake || hits_head {
+                dead_ids.push(*id);
+            }
+        }
+
+        for id in dead_ids.iter() {
+            let snake = self.snakes.remove(id).unwrap();
+            self.clear_snake(&snake);
+
+            self.snakes.insert(
+                *id,
+                Snake {
+                    body: VecDeque::new(),
+                    alive: false,
+                    score_changed: true,
+                    ..snake
+                },
+            );
+
+            new_heads.remove(id);
+        }
+
+        // Move the surviving snakes
+
+        let mut food_eaten = false;
+
+        for (id, head) in new_heads {
+            let snake = self.snakes.get_mut(&id).unwrap();
+
+            snake.body.push_front(head);
+            self.world.set_background_tile(head, snake_tile(id));
+
+            if self.food == Some(head) {
+                snake.growth += GROWTH_PER_FOOD;
+                snake.score += 1;
+                snake.score_changed = true;
+                food_eaten = true;
+            }
+
+            if snake.growth > 0 {
+                snake.growth -= 1;
+            } else {
+                let tail = snake.body.pop_back().unwrap();
+
+                // The head may have just moved into the previous tail
+                if !snake.body.contains(&tail) {
+                    self.world.set_background_tile(tail, &EMPTY_TILE);
+                }
+            }
+        }
+
+        if food_eaten || self.food.is_none() {
+            self.spawn_food();
+        }
+    }
+}
+
+lazy_static! {
+    static ref EMPTY_TILE: Tile = Tile::filled(8, 8, WHITE);
+    static ref FOOD_TILE: Tile = pattern_tile(|x, y| {
+        if (x as i32 - 3).abs() + (y as i32 - 3).abs() <= 2 {
+            DARK_GRAY
+        } else {
+            WHITE
+        }
+    });
+    static ref SNAKE_TILES: [Tile; 4] = [
+        Tile::filled(8, 8, BLACK),
+        Tile::filled(8, 8, DARK_GRAY),
+        pattern_tile(|x, y| if (x + y) % 2 == 0 { BLACK } else { WHITE }),
+        pattern_tile(|x, y| {
+            if x == 0 || y == 0 || x == 7 || y == 7 {
+                BLACK
+            } else {
+                WHITE
+            }
+        }),
+    ];
+}
+
+fn pattern_tile(pixel: fn(u8, u8) -> Color) -> Tile {
+    Tile::from_pixels(
+        8,
+        8,
+        (0..64).map(|index| pixel(index % 8, index / 8)).collect(),
+    )
+}
+
+// Each player gets a different pattern
+fn snake_tile(client_id: u8) -> &'static Tile {
+    &SNAKE_TILES[client_id as usize % SNAKE_TILES.len()]
+}
+
+impl App for SnakeApp {
+    fn update(&mut self, dt: &Duration, clients: &mut Vec<Client>) {
+        let area = *self.world.fit_client_screens(clients);
+
+        if area != self.arena {
+            self.arena = area;
+            self.setup_arena(clients);
+        }
+
+        // Each new client gets its own snake
+
+        for client in clients.iter() {
+            let needs_spawn = match self.snakes.get(&client.id()) {
+                Some(snake) => snake.respawn,
+                None => true,
+            };
+
+            if needs_spawn {
+                self.spawn_snake(client.id(), clients);
+            }
+        }
+
+        // Move the snakes at a fixed pace
+
+        self.time_since_last_step += *dt;
+
+        while self.time_since_last_step > STEP_DELAY {
+            self.step();
+            self.time_since_last_step -= STEP_DELAY;
+        }
+
+        // Every player sees their own score at the top of their screen
+
+        for client in clients.iter() {
+            if let Some(snake) = self.snakes.get_mut(&client.id()) {
+                if snake.score_changed {
+                    let status = if snake.alive { "" } else { " (START)" };
+                    let score = format!("P{} {}{}", client.id(), snake.score, status);
+
+                    match self.score_texts.get(&client.id()) {
+                        Some(text_id) => self.world.set_text(*text_id, &score),
+                        None => {
+                            let text_id = self.world.create_text(&score, font::default_font());
+                            let top_left = client.screen().bounding_box().mins;
+
+                            self.world.move_text(text_id, top_left.x, top_left.y);
+                            self.score_texts.insert(client.id(), text_id);
+                        }
+                    }
+
+                    snake.score_changed = false;
+                }
+            }
+        }
+
+        self.world.sync_clients(clients);
+    }
+
+    // The snake of a player who left would block the others
+    fn on_client_left(&mut self, client_id: u8) {
+        if let Some(snake) = self.snakes.remove(&client_id) {
+            self.clear_snake(&snake);
+        }
+
+        if let Some(text_id) = self.score_texts.remove(&client_id) {
+            self.world.delete_text(text_id);
+        }
+    }
+
+    fn on_input(&mut self, client_id: u8, event: &InputEvent) {
+        if event.kind != InputEventKind::Pressed {
+            return;
+        }
+
+        let snake = match self.snakes.get_mut(&client_id) {
+            Some(snake) => snake,
+            None => return,
+        };
+
+        // Dead snakes restart on the next update
+
+        if event.button == Button::Start && !snake.alive {
+            snake.respawn = true;
+            return;
+        }
+
+        let direction = match event.button {
+            Button::Up => Vector::new(0, -1),
+            Button::Down => Vector::new(0, 1),
+            Button::Left => Vector::new(-1, 0),
+            Button::Right => Vector::new(1, 0),
+            _ => return,
+        };
+
+        // Snakes cannot turn back on themselves
+
+        if direction != -snake.direction {
+            snake.next_direction = direction;
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Duration;
+
+use image::codecs::gif::GifDecoder;
+use image::{AnimationDecoder, DynamicImage};
+use parry2d::bounding_volume::AABB;
+
+use crate::apps::App;
+use crate::clients::client::Client;
+use crate::engine::assets::FileWatcher;
+use crate::engine::dithering::Conversion;
+use crate::engine::slicing::{self, ScreenTiles};
+use crate::{ServerCommand, VideoCommand};
+
+const DEFAULT_FRAME_DURATION: Duration = Duration::from_millis(100);
+
+struct Frame {
+    image: DynamicImage,
+    duration: Duration,
+}
+
+pub struct VideoApp {
+    frames: Vec<Frame>,
+    frame_index: usize,
+    time_in_frame: Duration,
+    paused: bool,
+    looping: bool,
+
+    // To reload the video when it's edited
+    source: Option<(String, Option<f32>)>,
+    watcher: FileWatcher,
+
+    // What is currently displayed, to only send the tiles that changed
+    displayed_frame: Option<usize>,
+    displayed_area: AABB,
+    screen_tiles: ScreenTiles,
+    conversion: Conversion,
+}
+
+impl VideoApp {
+    pub fn new() -> Self {
+        Self {
+            frames: Vec::new(),
+            frame_index: 0,
+            time_in_frame: Duration::ZERO,
+            paused: false,
+            looping: true,
+            source: None,
+            watcher: FileWatcher::new(),
+            displayed_frame: None,
+            displayed_area: AABB::new_invalid(),
+            screen_tiles: ScreenTiles::new(),
+            conversion: Conversion::default(),
+        }
+    }
+
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    pub fn load(&mut self, path: &str, fps: Option<f32>) {
+        let frames = if Path::new(path).is_dir() {
+            load_frames_directory(path)
+        } else {
+            load_gif(path)
+        };
+
+        match frames {
+            Ok(mut frames) if !frames.is_empty() => {
+                println!("video: {} frames from {}", frames.len(), path);
+
+                // A fixed framerate overrides the GIF's delays
+
+                if let Some(fps) = fps.filter(|fps| *fps > 0.0) {
+                    for frame in frames.iter_mut() {
+                        frame.duration = Duration::from_secs_f32(1.0 / fps);
+                    }
+                }
+
+                self.frames = frames;
+                self.seek(0);
+                self.paused = false;
+
+                self.source = Some((path.to_string(), fps));
+                self.watcher.unwatch_all();
+                self.watcher.watch(path);
+            }
+            Ok(_) => println!("No frames in {}", path),
+            Err(e) => println!("Cannot load video {}: {}", path, e),
+        }
+    }
+
+    // Keep playing from the same frame, only the tiles that changed are sent again
+    fn reload(&mut self) {
+        if let Some((path, fps)) = self.source.clone() {
+            let (frame_index, paused) = (self.frame_index, self.paused);
+
+            self.load(&path, fps);
+            self.seek(frame_index);
+            self.paused = paused;
+            self.displayed_frame = None;
+        }
+    }
+
+    fn seek(&mut self, frame_index: usize) {
+        self.frame_index = frame_index.min(self.frames.len().saturating_sub(1));
+        self.time_in_frame = Duration::ZERO;
+    }
+
+    fn advance(&mut self, dt: &Duration) {
+        if self.paused || self.frames.is_empty() {
+            return;
+        }
+
+        self.time_in_frame += *dt;
+
+        loop {
+            // Zero-length frames are skipped, but do not loop forever on them
+            let duration = self.frames[self.frame_index]
+                .duration
+                .max(Duration::from_millis(10));
+
+            if self.time_in_frame < duration {
+                break;
+            }
+
+            self.time_in_frame -= duration;
+
+            if self.frame_index + 1 < self.frames.len() {
+                self.frame_index += 1;
+            } else if self.looping {
+                self.frame_index = 0;
+            } else {
+                self.paused = true;
+                break;
+            }
+        }
+    }
+}
+
+fn load_gif(path: &str) -> Result<Vec<Frame>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let decoder = GifDecoder::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+
+    let frames = decoder
+        .into_frames()
+        .collect_frames()
+        .map_err(|e| e.to_string())?;
+
+    Ok(frames
+        .into_iter()
+        .map(|frame| Frame {
+            duration: Duration::from(frame.delay()),
+            image: DynamicImage::ImageRgba8(frame.into_buffer()),
+        })
+        .collect())
+}
+
+// Images named with their frame number, eg. frame-001.png
+fn load_frames_directory(path: &str) -> Result<Vec<Frame>, String> {
+    let mut numbered_paths = Vec::new();
+
+    for entry in fs::read_dir(path).map_err(|e| e.to_string())? {
+        let entry_path = entry.map_err(|e| e.to_string())?.path();
+
+        let file_name = match entry_path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(file_name) => file_name.to_string(),
+            None => continue,
+        };
+
+        let digits: String = file_name.chars().filter(|c| c.is_ascii_digit()).collect();
+
+        if let Ok(number) = digits.parse::<u64>() {
+            numbered_paths.push((number, entry_path));
+        }
+    }
+
+    numbered_paths.sort();
+
+    numbered_paths
+        .into_iter()
+        .map(|(_, frame_path)| {
+            Ok(Frame {
+                image: image::open(&frame_path).map_err(|e| format!("{frame_path:?}: {e}"))?,
+                duration: DEFAULT_FRAME_DURATION,
+            })
+        })
+        .collect()
+}
+
+impl App for VideoApp {
+    fn update(&mut self, dt: &Duration, clients: &mut Vec<Client>) {
+        if !self.watcher.poll(dt).is_empty() {
+            self.reload();
+        }
+
+        if self.frames.is_empty() {
+            return;
+        }
+
+        self.advance(dt);
+
+        let area = slicing::wall_area(clients);
+
+        // Redraw when the frame or the layout changes, for new clients, or to replace the
+        // approximate tiles
+
+        let redraw_all =
+            self.displayed_frame != Some(self.frame_index) || area != self.displayed_area;
+
+        for client in clients.iter_mut() {
+            if redraw_all || self.screen_tiles.needs_redraw(client) {
+                let frame = &self.frames[self.frame_index].image;
+                let image = slicing::screen_image(frame, &area, client.screen());
+                let (columns, tiles) =
+                    slicing::image_to_tiles(&self.conversion.convert_image(&image));
+
+                self.screen_tiles.draw(client, columns, tiles);
+            }
+        }
+
+        self.displayed_frame = Some(self.frame_index);
+        self.displayed_area = area;
+    }
+
+    // Draw again if the client comes back, its screen was reset
+    fn on_client_left(&mut self, client_id: u8) {
+        self.screen_tiles.remove(client_id);
+    }
+
+    fn process_server_command(&mut self, command: &ServerCommand) {
+        if let Some(conversion) = Conversion::from_command(command) {
+            self.conversion = conversion;
+            self.displayed_frame = None;
+        }
+
+        if let ServerCommand::Video { command } = command {
+            match command {
+                VideoCommand::Play { path, fps } => self.load(path, *fps),
+                VideoCommand::Pause => self.paused = true,
+                VideoCommand::Resume => self.paused = false,
+                VideoCommand::Seek { frame } => self.seek(*frame),
+                VideoCommand::Loop { enabled } => self.looping = *enabled,
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
 pub mod client;
 pub mod driver;
+pub mod input;
 pub mod screen;
 
 pub mod gameboy;
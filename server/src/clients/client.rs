@@ -4,31 +4,24 @@ use serde::{Deserialize, Serialize};
 
 use crate::clients::gameboy::GameBoyDriver;
 use crate::clients::gameboycolor::GameBoyColorDriver;
-use crate::clients::screen::Screen;
+use crate::clients::screen::{Bezel, Rotation, Screen};
+use crate::engine::dithering::Conversion;
+use crate::engine::font::{self, Font};
+use crate::engine::sound::Sound;
 use crate::engine::sprite::Sprite;
 use crate::engine::tile::Tile;
 use crate::ServerCommand;
 use core::panic;
 use std::fs;
 use std::io::{self, Read};
-use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{io::Write, net::TcpStream};
 
 use super::driver::Driver;
-
-pub enum Button {
-    Start,
-    Select,
-    B,
-    A,
-    Down,
-    Up,
-    Left,
-    Right,
-}
+use super::input::{Button, Input, InputEvent};
 
 pub type CommandData = Vec<u8>;
 
@@ -38,23 +31,37 @@ pub struct Client {
     driver: Box<dyn Driver + Send>,
 
     thread: JoinHandle<()>,
+    connected: Arc<AtomicBool>,
 
     unstaged_commands: Vec<CommandData>,
     staged_commands: Arc<Mutex<Vec<CommandData>>>,
 
-    // Bits: Start Select B A Down Up Left Right
-    inputs: Arc<Mutex<u8>>,
+    // Raw joypad bytes received by the thread, with their reception time
+    input_samples: Arc<Mutex<Vec<(Instant, u8)>>>,
+    input: Input,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct ClientAttributes {
     pub pos: (f32, f32),
+    #[serde(default)]
+    pub rotation: Rotation,
+    #[serde(default)]
+    pub mirrored: bool,
+    #[serde(default)]
+    pub size: Option<(f32, f32)>, // None for the driver's default
+    #[serde(default)]
+    pub bezel: Option<Bezel>,
 }
 
 impl ClientAttributes {
     fn new(client: &Client) -> Self {
         Self {
             pos: (client.screen().pos.x, client.screen().pos.y),
+            rotation: client.screen().rotation,
+            mirrored: client.screen().mirrored,
+            size: Some((client.screen().size.x, client.screen().size.y)),
+            bezel: Some(client.screen().bezel),
         }
     }
 }
@@ -98,6 +105,17 @@ impl Client {
                 Ok(attributes) => {
                     driver.screen_mut().pos.x = attributes.pos.0;
                     driver.screen_mut().pos.y = attributes.pos.1;
+                    driver.screen_mut().rotation = attributes.rotation;
+                    driver.screen_mut().mirrored = attributes.mirrored;
+
+                    if let Some(size) = attributes.size {
+                        driver.screen_mut().size.x = size.0;
+                        driver.screen_mut().size.y = size.1;
+                    }
+
+                    if let Some(bezel) = attributes.bezel {
+                        driver.screen_mut().bezel = bezel;
+                    }
                 }
                 Err(e) => {
                     println!("Cannot parse attributes: {}", e);
@@ -111,8 +129,11 @@ impl Client {
         let concurrent_staged_commands = Arc::new(Mutex::new(Vec::new()));
         let staged_commands = concurrent_staged_commands.clone();
 
-        let concurrent_inputs = Arc::new(Mutex::new(0u8));
-        let inputs = concurrent_inputs.clone();
+        let concurrent_input_samples = Arc::new(Mutex::new(Vec::new()));
+        let input_samples = concurrent_input_samples.clone();
+
+        let connected = Arc::new(AtomicBool::new(true));
+        let concurrent_connected = connected.clone();
 
         let thread = thread::spawn(move || {
             loop {
@@ -126,31 +147,48 @@ impl Client {
 
                     assert!(commands.len() < 0x10000); // 16 bits max
 
-                    stream
-                        .write(&[((commands.len() & 0xFF00) >> 8) as u8, commands.len() as u8])
-                        .unwrap();
+                    let count = [((commands.len() & 0xFF00) >> 8) as u8, commands.len() as u8];
 
                     // Then, the commands' data
 
-                    for command in commands.iter() {
-                        stream.write(command).unwrap();
-                    }
+                    let sent = stream.write_all(&count).and_then(|_| {
+                        commands
+                            .iter()
+                            .try_for_each(|command| stream.write_all(command))
+                    });
 
                     commands.clear();
+
+                    if let Err(e) = sent {
+                        println!("Client disconnected: {}", e);
+                        concurrent_connected.store(false, Ordering::SeqCst);
+                        return;
+                    }
                 }
 
                 // Receive inputs
+                // (nothing received = same state as before, keep the last known one)
 
-                let mut received_data = [0u8];
+                let mut received_data = [0u8; 16];
 
-                *concurrent_inputs.lock().unwrap() = match stream.read(&mut received_data) {
-                    Ok(_) => received_data[0],
+                match stream.read(&mut received_data) {
+                    Ok(0) => {
+                        println!("Client disconnected");
+                        concurrent_connected.store(false, Ordering::SeqCst);
+                        return;
+                    }
+                    Ok(count) => {
+                        let now = Instant::now();
+
+                        concurrent_input_samples
+                            .lock()
+                            .unwrap()
+                            .extend(received_data[..count].iter().map(|sample| (now, *sample)));
+                    }
                     Err(e) => {
                         if e.kind() != io::ErrorKind::WouldBlock {
                             println!("Client error: {}", e);
                         }
-
-                        0
                     }
                 };
 
@@ -162,9 +200,11 @@ impl Client {
             id,
             driver,
             thread,
+            connected,
             unstaged_commands: Vec::new(),
             staged_commands,
-            inputs,
+            input_samples,
+            input: Input::new(),
         }
     }
 
@@ -172,6 +212,11 @@ impl Client {
         self.id
     }
 
+    // False once the connection is lost, the client can then be dropped
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
     pub fn screen(&self) -> &Screen {
         &self.driver.screen()
     }
@@ -200,47 +245,110 @@ impl Client {
             ServerCommand::Pos { client_id, x, y } => {
                 if self.id == *client_id {
                     println!("client {}: pos to {} {}", self.id, x, y);
-                    self.driver.screen_mut().pos.x = *x;
-                    self.driver.screen_mut().pos.y = *y;
-
-                    // Save
-                    match serde_json::to_string(&ClientAttributes::new(self)) {
-                        Ok(json_string) => match fs::write(client_filename(self.id), json_string) {
-                            Err(e) => {
-                                println!("Cannot save client attributes: {}", e);
-                            }
-                            _ => {}
-                        },
-                        Err(e) => {
-                            println!("Cannot serialize attributes: {}", e);
-                        }
-                    }
+                    self.set_screen_position(*x, *y);
                 }
             }
 
+            ServerCommand::Orient {
+                client_id,
+                rotation,
+                mirrored,
+            } if self.id == *client_id => {
+                println!(
+                    "client {}: rotation to {:?}, mirrored {}",
+                    self.id, rotation, mirrored
+                );
+                self.set_screen_orientation(*rotation, *mirrored);
+            }
+
+            ServerCommand::Bezel {
+                client_id,
+                left,
+                top,
+                right,
+                bottom,
+            } if self.id == *client_id => {
+                println!(
+                    "client {}: bezel to {} {} {} {}",
+                    self.id, left, top, right, bottom
+                );
+                self.driver.screen_mut().bezel = Bezel {
+                    left: *left,
+                    top: *top,
+                    right: *right,
+                    bottom: *bottom,
+                };
+
+                self.save_attributes();
+            }
+
             _ => {}
         }
     }
 
-    pub fn button_pressed(&self, button: Button) -> bool {
-        let concurrent_inputs = self.inputs.lock().unwrap();
-
-        match button {
-            Button::Start => (*concurrent_inputs & 0x80) != 0,
-            Button::Select => (*concurrent_inputs & 0x40) != 0,
-            Button::B => (*concurrent_inputs & 0x20) != 0,
-            Button::A => (*concurrent_inputs & 0x10) != 0,
-            Button::Down => (*concurrent_inputs & 0x08) != 0,
-            Button::Up => (*concurrent_inputs & 0x04) != 0,
-            Button::Left => (*concurrent_inputs & 0x02) != 0,
-            Button::Right => (*concurrent_inputs & 0x01) != 0,
+    // Layout changes are saved right away
+
+    pub fn set_screen_position(&mut self, x: f32, y: f32) {
+        self.driver.screen_mut().pos.x = x;
+        self.driver.screen_mut().pos.y = y;
+
+        self.save_attributes();
+    }
+
+    // Size of the LCD, before rotation
+    pub fn set_screen_size(&mut self, width: f32, height: f32) {
+        self.driver.screen_mut().size.x = width;
+        self.driver.screen_mut().size.y = height;
+
+        self.save_attributes();
+    }
+
+    pub fn set_screen_orientation(&mut self, rotation: Rotation, mirrored: bool) {
+        self.driver.screen_mut().rotation = rotation;
+        self.driver.screen_mut().mirrored = mirrored;
+
+        self.save_attributes();
+    }
+
+    fn save_attributes(&self) {
+        match serde_json::to_string(&ClientAttributes::new(self)) {
+            Ok(json_string) => {
+                if let Err(e) = fs::write(client_filename(self.id), json_string) {
+                    println!("Cannot save client attributes: {}", e);
+                }
+            }
+            Err(e) => {
+                println!("Cannot serialize attributes: {}", e);
+            }
         }
     }
 
+    pub fn input(&self) -> &Input {
+        &self.input
+    }
+
+    pub fn button_pressed(&self, button: Button) -> bool {
+        self.input.is_held(button)
+    }
+
+    // Turn the samples received since the last call into input events
+    pub fn poll_input(&mut self) -> Vec<InputEvent> {
+        let samples: Vec<(Instant, u8)> = self.input_samples.lock().unwrap().drain(..).collect();
+
+        samples
+            .into_iter()
+            .flat_map(|(time, sample)| self.input.process_sample(sample, time))
+            .collect()
+    }
+
     //
 
-    pub fn draw_text(&mut self, text: &str, x: u32, y: u32) {
-        let commands = self.driver.draw_text(text, x, y);
+    pub fn draw_text(&mut self, text: &str, x: i32, y: i32) {
+        self.draw_text_with_font(text, &font::default_font(), x, y);
+    }
+
+    pub fn draw_text_with_font(&mut self, text: &str, font: &Font, x: i32, y: i32) {
+        let commands = self.driver.draw_text(text, font, x, y);
         self.buffer_commands(commands);
     }
 
@@ -249,8 +357,21 @@ impl Client {
         self.buffer_commands(commands);
     }
 
-    pub fn fill_screen_with_image(&mut self, image: &DynamicImage) {
-        let commands = self.driver.draw_image(image);
+    pub fn draw_tiles(&mut self, tiles: &[Tile], columns: usize, tile_x: i32, tile_y: i32) {
+        let commands = self.driver.draw_tiles(tiles, columns, tile_x, tile_y);
+        self.buffer_commands(commands);
+    }
+
+    pub fn is_background_tile_exact(&self, tile_x: u8, tile_y: u8) -> bool {
+        self.driver.is_background_tile_exact(tile_x, tile_y)
+    }
+
+    pub fn has_approximate_tiles(&self) -> bool {
+        self.driver.has_approximate_tiles()
+    }
+
+    pub fn fill_screen_with_image(&mut self, image: &DynamicImage, conversion: &Conversion) {
+        let commands = self.driver.draw_image(image, conversion);
         self.buffer_commands(commands);
     }
 
@@ -258,4 +379,36 @@ impl Client {
         let commands = self.driver.draw_sprite(id, sprite, x, y);
         self.buffer_commands(commands);
     }
+
+    pub fn hide_sprite(&mut self, id: usize) {
+        let commands = self.driver.hide_sprite(id);
+        self.buffer_commands(commands);
+    }
+
+    pub fn play_sound(&mut self, sound: &Sound) {
+        let commands = self.driver.play_sound(sound);
+        self.buffer_commands(commands);
+    }
+
+    pub fn stop_sound(&mut self, channel: u8) {
+        let commands = self.driver.stop_sound(channel);
+        self.buffer_commands(commands);
+    }
+
+    // Drops what the previous app had not sent yet
+    pub fn set_conversion(&mut self, conversion: &Conversion) {
+        self.driver.set_conversion(conversion);
+    }
+
+    pub fn reset(&mut self) {
+        self.unstaged_commands.clear();
+
+        let commands = self.driver.reset();
+        self.buffer_commands(commands);
+    }
+
+    pub fn fade(&mut self, amount: f32) {
+        let commands = self.driver.fade(amount);
+        self.buffer_commands(commands);
+    }
 }
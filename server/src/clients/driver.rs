@@ -1,6 +1,6 @@
 use image::DynamicImage;
 
-use crate::engine::{sprite::Sprite, tile::Tile};
+use crate::engine::{dithering::Conversion, font::Font, sound::Sound, sprite::Sprite, tile::Tile};
 
 use super::{client::CommandData, screen::Screen};
 
@@ -10,7 +10,8 @@ pub trait Driver {
 
     //
 
-    fn draw_text(&mut self, _text: &str, _x: u32, _y: u32) -> Vec<CommandData> {
+    // Position in pixels, can be partially offscreen
+    fn draw_text(&mut self, _text: &str, _font: &Font, _x: i32, _y: i32) -> Vec<CommandData> {
         unimplemented!()
     }
 
@@ -18,11 +19,57 @@ pub trait Driver {
         unimplemented!()
     }
 
-    fn draw_image(&mut self, _image: &DynamicImage) -> Vec<CommandData> {
+    // Rectangle of tiles, row by row, clipped to the screen
+    fn draw_tiles(
+        &mut self,
+        _tiles: &[Tile],
+        _columns: usize,
+        _tile_x: i32,
+        _tile_y: i32,
+    ) -> Vec<CommandData> {
+        Vec::new()
+    }
+
+    // Whether the cell shows its tile, and not the closest one as the tiles memory was full
+    fn is_background_tile_exact(&self, _tile_x: u8, _tile_y: u8) -> bool {
+        true
+    }
+
+    // Whether some cells show the closest tile, and redrawing them could now load the exact one
+    fn has_approximate_tiles(&self) -> bool {
+        false
+    }
+
+    fn draw_image(&mut self, _image: &DynamicImage, _conversion: &Conversion) -> Vec<CommandData> {
         unimplemented!()
     }
 
     fn draw_sprite(&mut self, id: usize, sprite: &Sprite, x: u8, y: u8) -> Vec<CommandData> {
         unimplemented!()
     }
+
+    fn hide_sprite(&mut self, _id: usize) -> Vec<CommandData> {
+        Vec::new()
+    }
+
+    fn play_sound(&mut self, _sound: &Sound) -> Vec<CommandData> {
+        Vec::new()
+    }
+
+    fn stop_sound(&mut self, _channel: u8) -> Vec<CommandData> {
+        Vec::new()
+    }
+
+    // How the tiles and sprites drawn from now on are converted to the screen's colors
+    fn set_conversion(&mut self, _conversion: &Conversion) {}
+
+    // Blank background, hidden sprites and no cached tiles, before another app draws
+    fn reset(&mut self) -> Vec<CommandData> {
+        Vec::new()
+    }
+
+    // Towards white, from 0 (normal colors) to 1 (all white)
+    fn fade(&mut self, _amount: f32) -> Vec<CommandData> {
+        Vec::new()
+    }
 }
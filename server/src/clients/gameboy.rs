@@ -1,73 +1,202 @@
 use std::{
-    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque},
     hash::{Hash, Hasher},
 };
 
-use crate::engine::{sprite::Sprite, tile::Tile};
+use crate::engine::{
+    dithering::{Conversion, SHADES},
+    font::Font,
+    slicing,
+    sound::Sound,
+    sprite::Sprite,
+    tile::Tile,
+};
 
-use super::{client::CommandData, driver::Driver, screen::Screen};
+use super::{
+    client::CommandData,
+    driver::Driver,
+    screen::{Bezel, Screen},
+};
 
-use image::DynamicImage;
-use log::info;
-use parry2d::math::{Point, Vector};
+use image::{imageops::FilterType, DynamicImage};
+use log::{error, info};
+use parry2d::math::Vector;
 
 pub struct GameBoyDriver {
     screen: Screen,
 
+    // VRAM tiles, the tile 0 being the blank one the background is filled with
     loaded_tile_indices: HashMap<u64, u8>,
-    next_tile_index: u8,
+    loaded_tiles: HashMap<u8, LoadedTile>,
+    free_tile_indices: Vec<u8>,
+    // Least recently used first
+    recent_tile_indices: VecDeque<u8>,
+
+    // Tiles shown by each background cell and sprite, displayed tiles are never recycled
+    tile_use_counts: [u16; 256],
+    background_tile_indices: HashMap<(u8, u8), u8>,
+    sprite_tile_indices: HashMap<u8, u8>,
+
+    // Cells showing the closest loaded tile, as the VRAM was full
+    approximate_cells: HashSet<(u8, u8)>,
 
-    loaded_sprite_indices: HashSet<usize>,
+    conversion: Conversion,
+}
+
+struct LoadedTile {
+    hash: u64,
+    is_sprite: bool,
+    shades: Vec<u8>,
 }
 
 impl GameBoyDriver {
     pub fn new() -> Self {
         Self {
-            screen: Screen {
-                pos: Point::new(0.0, 0.0),
-                size: Vector::new(4.8, 4.3), // TODO store as diagonal to avoid ratio inaccuracies?
-                res: Vector::new(160, 144),
-            },
+            // TODO store size as diagonal to avoid ratio inaccuracies?
+            // Original Game Boy, 9.0 x 14.8 cm with the LCD in the upper half
+            screen: Screen::new(Vector::new(4.8, 4.3), Vector::new(160, 144)).with_bezel(Bezel {
+                left: 2.1,
+                top: 2.4,
+                right: 2.1,
+                bottom: 8.1,
+            }),
             loaded_tile_indices: HashMap::new(),
-            next_tile_index: 1, // TEMP =1 to avoid filling bg, switch back to 0 later
-            loaded_sprite_indices: HashSet::new(),
+            loaded_tiles: HashMap::new(),
+            free_tile_indices: (1..=255).rev().collect(),
+            recent_tile_indices: VecDeque::new(),
+            tile_use_counts: [0; 256],
+            background_tile_indices: HashMap::new(),
+            sprite_tile_indices: HashMap::new(),
+            approximate_cells: HashSet::new(),
+            conversion: Conversion::default(),
         }
     }
 
-    fn load_tile_if_needed(&mut self, tile: &Tile) -> (Vec<CommandData>, u8) {
-        // TODO warn if size != 8x8
-        // TODO warn if tile_index > max
-
-        let mut commands = Vec::new();
+    // Sprite tiles differ from background ones by their transparent color. Once the VRAM is
+    // full of displayed tiles, the closest loaded one is returned instead, as not exact.
+    fn load_tile_if_needed(
+        &mut self,
+        tile: &Tile,
+        is_sprite: bool,
+    ) -> (Vec<CommandData>, u8, bool) {
+        // The hardware tiles are 8x8, others are left blank
+        if tile.size.x != 8 || tile.size.y != 8 {
+            error!(
+                "cannot draw a {}x{} tile, 8x8 expected",
+                tile.size.x, tile.size.y
+            );
+            return (Vec::new(), 0, true);
+        }
 
         // Re-use the tile if it has already been loaded
 
         let mut hasher = DefaultHasher::new();
         tile.hash(&mut hasher);
+        is_sprite.hash(&mut hasher);
         let tile_hash = hasher.finish();
 
-        let tile_index = match self.loaded_tile_indices.get(&tile_hash) {
-            Some(tile_index) => *tile_index,
+        if let Some(tile_index) = self.loaded_tile_indices.get(&tile_hash).copied() {
+            self.mark_tile_recent(tile_index);
+            return (Vec::new(), tile_index, true);
+        }
+
+        let shades = tile_shades(&self.conversion.convert_tile(tile), is_sprite);
+
+        let tile_index = match self.allocate_tile_index() {
+            Some(tile_index) => tile_index,
             None => {
-                let this_tile_index = self.next_tile_index;
+                return (
+                    Vec::new(),
+                    self.closest_tile_index(&shades, is_sprite),
+                    false,
+                )
+            }
+        };
 
-                info!("loading tile");
+        info!("loading tile");
 
-                commands.push(command_load_tiles(
-                    true,
-                    this_tile_index as u16, /* TODO u8 good enough? */
-                    1,
-                    tile_to_gb(tile),
-                ));
+        let commands = vec![command_load_tiles(
+            !is_sprite,
+            tile_index as u16,
+            1,
+            shades_to_gb(&shades),
+        )];
+
+        self.loaded_tile_indices.insert(tile_hash, tile_index);
+        self.loaded_tiles.insert(
+            tile_index,
+            LoadedTile {
+                hash: tile_hash,
+                is_sprite,
+                shades,
+            },
+        );
+        self.recent_tile_indices.push_back(tile_index);
+
+        (commands, tile_index, true)
+    }
+
+    // A free index, or the least recently used tile not displayed anymore
+    fn allocate_tile_index(&mut self) -> Option<u8> {
+        if let Some(tile_index) = self.free_tile_indices.pop() {
+            return Some(tile_index);
+        }
+
+        let position = self
+            .recent_tile_indices
+            .iter()
+            .position(|tile_index| self.tile_use_counts[*tile_index as usize] == 0)?;
 
-                self.next_tile_index = self.next_tile_index + 1;
-                self.loaded_tile_indices.insert(tile_hash, this_tile_index);
+        let tile_index = self.recent_tile_indices.remove(position)?;
 
-                this_tile_index
+        // The same tile may have been loaded again since a conversion change
+
+        if let Some(recycled_tile) = self.loaded_tiles.remove(&tile_index) {
+            if self.loaded_tile_indices.get(&recycled_tile.hash) == Some(&tile_index) {
+                self.loaded_tile_indices.remove(&recycled_tile.hash);
             }
+        }
+
+        Some(tile_index)
+    }
+
+    fn mark_tile_recent(&mut self, tile_index: u8) {
+        if let Some(position) = self
+            .recent_tile_indices
+            .iter()
+            .position(|recent_index| *recent_index == tile_index)
+        {
+            self.recent_tile_indices.remove(position);
+            self.recent_tile_indices.push_back(tile_index);
+        }
+    }
+
+    // Loaded tile of the same kind with the least shade differences, or the blank tile
+    fn closest_tile_index(&self, shades: &[u8], is_sprite: bool) -> u8 {
+        let difference = |other_shades: &[u8]| -> u32 {
+            shades
+                .iter()
+                .zip(other_shades)
+                .map(|(shade, other_shade)| shade.abs_diff(*other_shade) as u32)
+                .sum()
         };
 
-        (commands, tile_index)
+        self.loaded_tiles
+            .iter()
+            .filter(|(_, loaded_tile)| loaded_tile.is_sprite == is_sprite)
+            .map(|(tile_index, loaded_tile)| (*tile_index, difference(&loaded_tile.shades)))
+            .chain([(0, difference(&[0; 64]))])
+            .min_by_key(|(_, difference)| *difference)
+            .map_or(0, |(tile_index, _)| tile_index)
+    }
+
+    fn use_tile(&mut self, tile_index: u8) {
+        self.tile_use_counts[tile_index as usize] += 1;
+    }
+
+    fn release_tile(&mut self, tile_index: u8) {
+        self.tile_use_counts[tile_index as usize] =
+            self.tile_use_counts[tile_index as usize].saturating_sub(1);
     }
 }
 
@@ -82,59 +211,220 @@ impl Driver for GameBoyDriver {
 
     // High-level commands
 
-    fn draw_text(&mut self, text: &str, x: u32, y: u32) -> Vec<CommandData> {
-        vec![command_draw_text(text, x, y)]
+    fn draw_text(&mut self, text: &str, font: &Font, x: i32, y: i32) -> Vec<CommandData> {
+        let text_tiles = font
+            .rasterize(text)
+            .to_tiles(x.rem_euclid(8) as usize, y.rem_euclid(8) as usize);
+
+        self.draw_tiles(
+            &text_tiles.tiles,
+            text_tiles.columns,
+            x.div_euclid(8),
+            y.div_euclid(8),
+        )
     }
 
     fn draw_tile(&mut self, tile: &Tile, x: u8, y: u8) -> Vec<CommandData> {
-        // Load the tile
+        self.draw_tiles(
+            std::slice::from_ref(tile),
+            1,
+            (x / 8) as i32,
+            (y / 8) as i32,
+        )
+    }
 
-        let (mut commands, tile_index) = self.load_tile_if_needed(tile);
+    fn draw_tiles(
+        &mut self,
+        tiles: &[Tile],
+        columns: usize,
+        tile_x: i32,
+        tile_y: i32,
+    ) -> Vec<CommandData> {
+        let mut commands = Vec::new();
 
-        // Draw the tile
+        if columns == 0 {
+            return commands;
+        }
 
-        commands.push(command_set_background_tiles(
-            x / 8,
-            y / 8,
-            1,
-            1,
-            vec![tile_index],
-        ));
+        // Only keep the tiles on the screen
+
+        let screen_columns = (self.screen.res.x / 8) as i32;
+        let screen_rows = (self.screen.res.y / 8) as i32;
+
+        let rows = tiles.len() / columns;
+
+        let visible_columns =
+            (tile_x.max(0)..(tile_x + columns as i32).min(screen_columns)).collect::<Vec<i32>>();
+        let visible_rows =
+            (tile_y.max(0)..(tile_y + rows as i32).min(screen_rows)).collect::<Vec<i32>>();
+
+        if visible_columns.is_empty() || visible_rows.is_empty() {
+            return commands;
+        }
+
+        // Load and draw the tiles row by row. The tiles a row showed can only be recycled once
+        // it is drawn, for the following rows.
+
+        for y in visible_rows.iter() {
+            let mut tile_indices = Vec::with_capacity(visible_columns.len());
+            let mut replaced_tile_indices = Vec::with_capacity(visible_columns.len());
+
+            for x in visible_columns.iter() {
+                let tile = &tiles[(y - tile_y) as usize * columns + (x - tile_x) as usize];
+
+                let (load_commands, tile_index, is_exact) = self.load_tile_if_needed(tile, false);
+                commands.extend(load_commands);
+                tile_indices.push(tile_index);
+
+                let cell = (*x as u8, *y as u8);
+
+                self.use_tile(tile_index);
+                replaced_tile_indices.extend(self.background_tile_indices.insert(cell, tile_index));
+
+                if is_exact {
+                    self.approximate_cells.remove(&cell);
+                } else {
+                    self.approximate_cells.insert(cell);
+                }
+            }
+
+            commands.push(command_set_background_tiles(
+                visible_columns[0] as u8,
+                *y as u8,
+                visible_columns.len() as u8,
+                1,
+                tile_indices,
+            ));
+
+            for tile_index in replaced_tile_indices {
+                self.release_tile(tile_index);
+            }
+        }
 
         commands
     }
 
+    fn is_background_tile_exact(&self, tile_x: u8, tile_y: u8) -> bool {
+        !self.approximate_cells.contains(&(tile_x, tile_y))
+    }
+
+    fn has_approximate_tiles(&self) -> bool {
+        let has_room = !self.free_tile_indices.is_empty()
+            || self
+                .recent_tile_indices
+                .iter()
+                .any(|tile_index| self.tile_use_counts[*tile_index as usize] == 0);
+
+        !self.approximate_cells.is_empty() && has_room
+    }
+
     // TODO add x, y params
-    fn draw_image(&mut self, image: &DynamicImage) -> Vec<CommandData> {
-        todo!()
+    fn draw_image(&mut self, image: &DynamicImage, conversion: &Conversion) -> Vec<CommandData> {
+        // The image is oriented as the wall, not as the device
+
+        let world_res = self.screen.world_res();
+
+        let resized_image =
+            image.resize_exact(world_res.x as u32, world_res.y as u32, FilterType::Triangle);
+
+        let oriented_image = self.screen.orient_image(&resized_image);
+
+        let (columns, tiles) = slicing::image_to_tiles(&conversion.convert_image(&oriented_image));
+
+        self.draw_tiles(&tiles, columns, 0, 0)
     }
 
     fn draw_sprite(&mut self, id: usize, sprite: &Sprite, x: u8, y: u8) -> Vec<CommandData> {
         // Load the sprite's tile
 
-        let (mut commands, tile_index) = self.load_tile_if_needed(&sprite.tile);
+        let (mut commands, tile_index, _) = self.load_tile_if_needed(&sprite.tile, true);
+
+        self.use_tile(tile_index);
+
+        if let Some(replaced_tile_index) = self.sprite_tile_indices.insert(id as u8, tile_index) {
+            self.release_tile(replaced_tile_index);
+        }
 
         // Draw the sprite
 
         // TODO if needed only
-        commands.push(command_set_sprite_tile(id as u8, tile_index)); // TODO dangerous usize to u8 cast
+        commands.push(command_set_sprite_tile(id as u8, tile_index)); // IDs are below the 40 hardware sprites
 
-        commands.push(command_set_sprite_position(id as u8, x, y));
+        // The hardware sprite position is offset by (8, 16) so that sprites can be partially offscreen
+        commands.push(command_set_sprite_position(
+            id as u8,
+            x.saturating_add(8),
+            y.saturating_add(16),
+        ));
 
         commands
     }
-}
 
-// Low-level commands
+    fn hide_sprite(&mut self, id: usize) -> Vec<CommandData> {
+        // Its tile can be recycled
+        if let Some(tile_index) = self.sprite_tile_indices.remove(&(id as u8)) {
+            self.release_tile(tile_index);
+        }
 
-fn command_draw_text(text: &str, x: u32, y: u32) -> Vec<u8> {
-    let mut data = vec![0u8, x as u8, y as u8, text.len() as u8];
-    for char in text.chars() {
-        data.push(char as u8);
+        // Sprites at (0, 0) are out of the visible area
+        vec![command_set_sprite_position(id as u8, 0, 0)]
+    }
+
+    fn play_sound(&mut self, sound: &Sound) -> Vec<CommandData> {
+        let mut commands = Vec::new();
+
+        // The wave channel plays whatever is in the wave RAM
+
+        if let Some(wave_ram) = sound.wave_ram() {
+            commands.push(command_load_wave(&wave_ram));
+        }
+
+        commands.push(command_play_sound(sound.channel(), &sound.registers()));
+
+        commands
+    }
+
+    fn stop_sound(&mut self, channel: u8) -> Vec<CommandData> {
+        vec![command_stop_sound(channel)]
+    }
+
+    fn set_conversion(&mut self, conversion: &Conversion) {
+        // The loaded tiles are only kept for the cells still showing them
+        if *conversion != self.conversion {
+            self.conversion = *conversion;
+            self.loaded_tile_indices.clear();
+        }
+    }
+
+    fn reset(&mut self) -> Vec<CommandData> {
+        // The ROM blanks the tile 0 to fill the background with it
+        self.loaded_tile_indices.clear();
+        self.loaded_tiles.clear();
+        self.free_tile_indices = (1..=255).rev().collect();
+        self.recent_tile_indices.clear();
+
+        self.tile_use_counts = [0; 256];
+        self.background_tile_indices.clear();
+        self.sprite_tile_indices.clear();
+        self.approximate_cells.clear();
+
+        vec![command_reset()]
+    }
+
+    fn fade(&mut self, amount: f32) -> Vec<CommandData> {
+        // Each step shifts the 4 shades one step lighter
+        let steps = (amount.clamp(0.0, 1.0) * 3.0).round() as u8;
+
+        let palette = (0..4u8).fold(0, |palette, shade| {
+            palette | shade.saturating_sub(steps) << (shade * 2)
+        });
+
+        vec![command_set_palettes(palette, palette)]
     }
-    data
 }
 
+// Low-level commands
+
 fn command_load_tiles(
     is_background: bool,
     tile_index: u16,
@@ -181,20 +471,55 @@ fn command_set_sprite_position(sprite_index: u8, x: u8, y: u8) -> Vec<u8> {
     vec![4u8, sprite_index, x, y]
 }
 
-// Helpers
+fn command_play_sound(channel: u8, registers: &[u8; 5]) -> Vec<u8> {
+    let mut data = vec![5u8, channel];
+    data.extend_from_slice(registers);
+    data
+}
 
-fn tile_to_gb(tile: &Tile) -> Vec<u8> {
-    let mut gb_tile = vec![0; 8 * 2];
+fn command_load_wave(wave_ram: &[u8; 16]) -> Vec<u8> {
+    let mut data = vec![6u8];
+    data.extend_from_slice(wave_ram);
+    data
+}
+
+fn command_stop_sound(channel: u8) -> Vec<u8> {
+    vec![7u8, channel]
+}
+
+fn command_reset() -> Vec<u8> {
+    vec![8u8]
+}
 
-    for (pixel_index, color) in tile.pixels.iter().enumerate() {
-        // RGB to grayscale
+fn command_set_palettes(background_palette: u8, sprite_palette: u8) -> Vec<u8> {
+    vec![9u8, background_palette, sprite_palette]
+}
 
-        let luminance = 0.2126 * (color.r as f32 / 255.0)
-            + 0.7152 * (color.g as f32 / 255.0)
-            + 0.0722 * (color.b as f32 / 255.0);
+// Helpers
 
-        let grayscale = ((1.0 - luminance) * 4.0).clamp(0.0, 3.0) as u8; // TODO rounding errors, sometimes = 4
+// Shade index of each pixel of a converted tile. Sprites use color 0 for transparent pixels, so
+// opaque white is drawn as light gray, the closest of the 3 other colors.
+fn tile_shades(tile: &Tile, is_sprite: bool) -> Vec<u8> {
+    tile.pixels
+        .iter()
+        .map(|color| {
+            let shade = SHADES.iter().position(|shade| shade == color).unwrap_or(0) as u8;
+
+            if color.is_transparent() {
+                0
+            } else if is_sprite {
+                shade.max(1)
+            } else {
+                shade
+            }
+        })
+        .collect()
+}
+
+fn shades_to_gb(shades: &[u8]) -> Vec<u8> {
+    let mut gb_tile = vec![0; 8 * 2];
 
+    for (pixel_index, grayscale) in shades.iter().enumerate() {
         // To GB tile format
 
         let pixel_y = pixel_index / 8;
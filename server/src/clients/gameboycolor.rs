@@ -1,7 +1,7 @@
 use super::{client::CommandData, driver::Driver, screen::Screen};
 
 use image::DynamicImage;
-use parry2d::math::{Point, Vector};
+use parry2d::math::Vector;
 
 pub struct GameBoyColorDriver {
     screen: Screen,
@@ -10,11 +10,8 @@ pub struct GameBoyColorDriver {
 impl GameBoyColorDriver {
     pub fn new() -> Self {
         Self {
-            screen: Screen {
-                pos: Point::new(0.0, 0.0),
-                size: Vector::new(4.8, 4.3), // TODO store as diagonal to avoid ratio inaccuracies?
-                res: Vector::new(160, 144),
-            },
+            // TODO store size as diagonal to avoid ratio inaccuracies?
+            screen: Screen::new(Vector::new(4.8, 4.3), Vector::new(160, 144)),
         }
     }
 }
@@ -0,0 +1,163 @@
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    Start,
+    Select,
+    B,
+    A,
+    Down,
+    Up,
+    Left,
+    Right,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Start,
+        Button::Select,
+        Button::B,
+        Button::A,
+        Button::Down,
+        Button::Up,
+        Button::Left,
+        Button::Right,
+    ];
+
+    // Bit in the joypad byte sent by the ROM
+    pub fn mask(&self) -> u8 {
+        match self {
+            Button::Start => 0x80,
+            Button::Select => 0x40,
+            Button::B => 0x20,
+            Button::A => 0x10,
+            Button::Down => 0x08,
+            Button::Up => 0x04,
+            Button::Left => 0x02,
+            Button::Right => 0x01,
+        }
+    }
+
+    fn index(&self) -> usize {
+        self.mask().trailing_zeros() as usize
+    }
+}
+
+// Set of buttons, stored with the same layout as the joypad byte
+// (Start Select B A Down Up Left Right)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Buttons(pub u8);
+
+impl Buttons {
+    pub fn from_buttons(buttons: &[Button]) -> Self {
+        Self(buttons.iter().fold(0, |bits, button| bits | button.mask()))
+    }
+
+    pub fn contains(&self, button: Button) -> bool {
+        self.0 & button.mask() != 0
+    }
+
+    pub fn contains_all(&self, other: Buttons) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Button> + '_ {
+        Button::ALL
+            .into_iter()
+            .filter(move |button| self.contains(*button))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEventKind {
+    Pressed,
+    Released { held: Duration },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InputEvent {
+    pub button: Button,
+    pub kind: InputEventKind,
+    pub time: Instant,
+
+    // Every button held right after this event, to detect combos
+    pub held_buttons: Buttons,
+}
+
+impl InputEvent {
+    pub fn is_press(&self, button: Button) -> bool {
+        self.button == button && self.kind == InputEventKind::Pressed
+    }
+
+    pub fn is_release(&self, button: Button) -> bool {
+        self.button == button && matches!(self.kind, InputEventKind::Released { .. })
+    }
+
+    // True if this press completes the given combo
+    pub fn completes_combo(&self, combo: &[Button]) -> bool {
+        self.kind == InputEventKind::Pressed
+            && combo.contains(&self.button)
+            && self.held_buttons.contains_all(Buttons::from_buttons(combo))
+    }
+}
+
+// Last known joypad state of a client, turned into pressed/released events
+// from the raw samples received by the client thread
+pub struct Input {
+    state: Buttons,
+    pressed_since: [Option<Instant>; 8],
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Self {
+            state: Buttons::default(),
+            pressed_since: [None; 8],
+        }
+    }
+
+    pub fn state(&self) -> Buttons {
+        self.state
+    }
+
+    pub fn is_held(&self, button: Button) -> bool {
+        self.state.contains(button)
+    }
+
+    pub fn held_duration(&self, button: Button) -> Option<Duration> {
+        self.pressed_since[button.index()].map(|since| since.elapsed())
+    }
+
+    pub fn process_sample(&mut self, sample: u8, time: Instant) -> Vec<InputEvent> {
+        let new_state = Buttons(sample);
+        let changed = Buttons(self.state.0 ^ new_state.0);
+
+        let mut events = Vec::new();
+
+        for button in changed.iter() {
+            let kind = if new_state.contains(button) {
+                self.pressed_since[button.index()] = Some(time);
+
+                InputEventKind::Pressed
+            } else {
+                let held = match self.pressed_since[button.index()].take() {
+                    Some(since) => time.saturating_duration_since(since),
+                    None => Duration::ZERO,
+                };
+
+                InputEventKind::Released { held }
+            };
+
+            events.push(InputEvent {
+                button,
+                kind,
+                time,
+                held_buttons: new_state,
+            });
+        }
+
+        self.state = new_state;
+
+        events
+    }
+}
@@ -1,19 +1,244 @@
+use image::DynamicImage;
 use parry2d::bounding_volume::AABB;
 use parry2d::math::{Point, Vector};
+use serde::{Deserialize, Serialize};
 use std::ops::Add;
 
+use crate::engine::tile::Tile;
+
+// Clockwise rotation of the device, as mounted on the wall
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rotation {
+    #[default]
+    #[value(name = "0")]
+    #[serde(rename = "0")]
+    Deg0,
+    #[value(name = "90")]
+    #[serde(rename = "90")]
+    Deg90,
+    #[value(name = "180")]
+    #[serde(rename = "180")]
+    Deg180,
+    #[value(name = "270")]
+    #[serde(rename = "270")]
+    Deg270,
+}
+
+impl Rotation {
+    pub fn degrees(&self) -> u16 {
+        match self {
+            Rotation::Deg0 => 0,
+            Rotation::Deg90 => 90,
+            Rotation::Deg180 => 180,
+            Rotation::Deg270 => 270,
+        }
+    }
+
+    pub fn clockwise(&self) -> Rotation {
+        match self {
+            Rotation::Deg0 => Rotation::Deg90,
+            Rotation::Deg90 => Rotation::Deg180,
+            Rotation::Deg180 => Rotation::Deg270,
+            Rotation::Deg270 => Rotation::Deg0,
+        }
+    }
+}
+
+// Device casing around the LCD, in world units, on each side of the device's own frame
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Bezel {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl Bezel {
+    // Sides as seen on the wall (left, top, right, bottom)
+    fn world_sides(&self, rotation: Rotation, mirrored: bool) -> (f32, f32, f32, f32) {
+        let (left, right) = if mirrored {
+            (self.right, self.left)
+        } else {
+            (self.left, self.right)
+        };
+
+        match rotation {
+            Rotation::Deg0 => (left, self.top, right, self.bottom),
+            Rotation::Deg90 => (self.bottom, left, self.top, right),
+            Rotation::Deg180 => (right, self.bottom, left, self.top),
+            Rotation::Deg270 => (self.top, right, self.bottom, left),
+        }
+    }
+}
+
 pub struct Screen {
-    pub pos: Point<f32>,
+    pub pos: Point<f32>,   // Top-left of the visible LCD area on the wall
     pub size: Vector<f32>, // TODO store as diagonal to avoid ratio inaccuracies?
     pub res: Vector<usize>,
+
+    // Size, resolution and bezel are the device's own, before rotation
+    pub rotation: Rotation,
+    pub mirrored: bool, // Flipped horizontally, in the device's own frame
+    pub bezel: Bezel,
 }
 
 impl Screen {
+    pub fn new(size: Vector<f32>, res: Vector<usize>) -> Self {
+        Self {
+            pos: Point::new(0.0, 0.0),
+            size,
+            res,
+            rotation: Rotation::Deg0,
+            mirrored: false,
+            bezel: Bezel::default(),
+        }
+    }
+
+    pub fn with_bezel(mut self, bezel: Bezel) -> Self {
+        self.bezel = bezel;
+        self
+    }
+
+    fn is_sideways(&self) -> bool {
+        matches!(self.rotation, Rotation::Deg90 | Rotation::Deg270)
+    }
+
+    // Size of the footprint on the wall
+    pub fn world_size(&self) -> Vector<f32> {
+        if self.is_sideways() {
+            Vector::new(self.size.y, self.size.x)
+        } else {
+            self.size
+        }
+    }
+
+    // Resolution along the world axes
+    pub fn world_res(&self) -> Vector<usize> {
+        if self.is_sideways() {
+            Vector::new(self.res.y, self.res.x)
+        } else {
+            self.res
+        }
+    }
+
+    // Visible LCD area
     pub fn bounding_box(&self) -> AABB {
-        AABB::new(self.pos, self.pos.add(self.size))
+        AABB::new(self.pos, self.pos.add(self.world_size()))
+    }
+
+    // Whole device, bezel included
+    pub fn footprint(&self) -> AABB {
+        let (left, top, right, bottom) = self.bezel.world_sides(self.rotation, self.mirrored);
+        let lcd = self.bounding_box();
+
+        AABB::new(
+            Point::new(lcd.mins.x - left, lcd.mins.y - top),
+            Point::new(lcd.maxs.x + right, lcd.maxs.y + bottom),
+        )
+    }
+
+    // World size of an 8x8 tile
+    pub fn tile_size(&self) -> Vector<f32> {
+        let world_size = self.world_size();
+        let world_res = self.world_res();
+
+        Vector::new(
+            world_size.x / world_res.x as f32 * 8.0,
+            world_size.y / world_res.y as f32 * 8.0,
+        )
     }
 
     pub fn contains(&self, point: &Point<f32>) -> bool {
         self.bounding_box().contains_local_point(point)
     }
+
+    // Normalized (0-1) position in the footprint to normalized position on the device
+    fn to_device(&self, u: f32, v: f32) -> (f32, f32) {
+        let (s, t) = match self.rotation {
+            Rotation::Deg0 => (u, v),
+            Rotation::Deg90 => (v, 1.0 - u),
+            Rotation::Deg180 => (1.0 - u, 1.0 - v),
+            Rotation::Deg270 => (1.0 - v, u),
+        };
+
+        if self.mirrored {
+            (1.0 - s, t)
+        } else {
+            (s, t)
+        }
+    }
+
+    // World position to device pixels, unclamped
+    pub fn to_pixels(&self, world_pos: &Point<f32>) -> Point<f32> {
+        let world_size = self.world_size();
+
+        let (s, t) = self.to_device(
+            (world_pos.x - self.pos.x) / world_size.x,
+            (world_pos.y - self.pos.y) / world_size.y,
+        );
+
+        Point::new(s * self.res.x as f32, t * self.res.y as f32)
+    }
+
+    // Top-left device pixel of a world rectangle, whichever of its corners ends up there
+    pub fn rect_to_pixels(&self, world_pos: &Point<f32>, world_size: &Vector<f32>) -> Point<f32> {
+        let a = self.to_pixels(world_pos);
+        let b = self.to_pixels(&world_pos.add(world_size));
+
+        Point::new(a.x.min(b.x), a.y.min(b.y))
+    }
+
+    // Reorder world-oriented pixels as displayed by the device, returning its width and height
+    pub fn orient_pixels<T: Clone>(
+        &self,
+        pixels: &[T],
+        width: usize,
+        height: usize,
+    ) -> (usize, usize, Vec<T>) {
+        let (device_width, device_height) = if self.is_sideways() {
+            (height, width)
+        } else {
+            (width, height)
+        };
+
+        let mut device_pixels = pixels.to_vec();
+
+        for y in 0..height {
+            for x in 0..width {
+                let (s, t) = self.to_device(
+                    (x as f32 + 0.5) / width as f32,
+                    (y as f32 + 0.5) / height as f32,
+                );
+
+                let device_x = (s * device_width as f32) as usize;
+                let device_y = (t * device_height as f32) as usize;
+
+                device_pixels[device_y * device_width + device_x] = pixels[y * width + x].clone();
+            }
+        }
+
+        (device_width, device_height, device_pixels)
+    }
+
+    pub fn orient_tile(&self, tile: &Tile) -> Tile {
+        let (width, height, pixels) =
+            self.orient_pixels(&tile.pixels, tile.size.x as usize, tile.size.y as usize);
+
+        Tile::from_pixels(width as u8, height as u8, pixels)
+    }
+
+    pub fn orient_image(&self, image: &DynamicImage) -> DynamicImage {
+        let image = match self.rotation {
+            Rotation::Deg0 => image.clone(),
+            Rotation::Deg90 => image.rotate270(),
+            Rotation::Deg180 => image.rotate180(),
+            Rotation::Deg270 => image.rotate90(),
+        };
+
+        if self.mirrored {
+            image.fliph()
+        } else {
+            image
+        }
+    }
 }
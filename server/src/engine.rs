@@ -1,4 +1,13 @@
+pub mod assets;
 pub mod color;
+pub mod dithering;
+pub mod font;
+pub mod markers;
+pub mod music;
+pub mod slicing;
+pub mod sound;
 pub mod sprite;
+pub mod text;
 pub mod tile;
+pub mod tiled;
 pub mod world;
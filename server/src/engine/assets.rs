@@ -0,0 +1,424 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use image::{DynamicImage, GenericImageView};
+use serde::Deserialize;
+
+use super::color::{Color, TRANSPARENT};
+use super::tile::Tile;
+use super::world::MAX_SPRITES;
+
+// 8x8 tiles cut from a spritesheet, row by row, the size of the hardware tiles. Larger
+// sprites are made of several tiles. Incomplete tiles on the right and bottom edges are
+// ignored.
+pub fn load_tileset(path: &str) -> Result<Vec<Tile>, String> {
+    let image = image::open(path).map_err(|e| format!("{path}: {e}"))?;
+
+    Ok(cut_tiles(&image))
+}
+
+pub fn cut_tiles(image: &DynamicImage) -> Vec<Tile> {
+    let columns = image.width() / 8;
+    let rows = image.height() / 8;
+
+    let mut tiles = Vec::with_capacity((columns * rows) as usize);
+
+    for row in 0..rows {
+        for column in 0..columns {
+            tiles.push(crop_tile(image, column * 8, row * 8, 8, 8));
+        }
+    }
+
+    tiles
+}
+
+pub fn crop_tile(image: &DynamicImage, x: u32, y: u32, width: u8, height: u8) -> Tile {
+    let pixels = (0..height as u32 * width as u32)
+        .map(|index| {
+            let pixel = image.get_pixel(x + index % width as u32, y + index / width as u32);
+
+            Color::rgba(pixel[0], pixel[1], pixel[2], pixel[3])
+        })
+        .collect();
+
+    Tile::from_pixels(width, height, pixels)
+}
+
+// How often the watched files are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Files polled for changes, to reload the assets while the server runs
+pub struct FileWatcher {
+    modified_times: HashMap<PathBuf, Option<SystemTime>>,
+    since_last_poll: Duration,
+}
+
+impl FileWatcher {
+    pub fn new() -> Self {
+        Self {
+            modified_times: HashMap::new(),
+            since_last_poll: Duration::ZERO,
+        }
+    }
+
+    pub fn watch(&mut self, path: &str) {
+        let path = PathBuf::from(path);
+        let modified_time = modified_time(&path);
+
+        self.modified_times.insert(path, modified_time);
+    }
+
+    pub fn unwatch_all(&mut self) {
+        self.modified_times.clear();
+    }
+
+    // Files modified since the last poll, checked every POLL_INTERVAL
+    pub fn poll(&mut self, dt: &Duration) -> Vec<PathBuf> {
+        self.since_last_poll += *dt;
+
+        if self.since_last_poll < POLL_INTERVAL {
+            return Vec::new();
+        }
+
+        self.since_last_poll = Duration::ZERO;
+
+        let mut changed_paths = Vec::new();
+
+        for (path, known_time) in self.modified_times.iter_mut() {
+            let modified_time = modified_time(path);
+
+            if modified_time != *known_time {
+                *known_time = modified_time;
+                changed_paths.push(path.clone());
+            }
+        }
+
+        changed_paths
+    }
+}
+
+// Latest modification of a file, or of the files in a directory. None while it's missing,
+// eg. when an editor replaces it.
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified_time = metadata.modified().ok()?;
+
+    if !metadata.is_dir() {
+        return Some(modified_time);
+    }
+
+    fs::read_dir(path)
+        .ok()?
+        .filter_map(|entry| entry.ok()?.metadata().ok()?.modified().ok())
+        .chain(std::iter::once(modified_time))
+        .max()
+}
+
+// Tiles of a spritesheet, cut again when the file changes
+pub struct WatchedTileset {
+    path: String,
+    watcher: FileWatcher,
+
+    pub tiles: Vec<Tile>,
+}
+
+impl WatchedTileset {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut watcher = FileWatcher::new();
+        watcher.watch(path);
+
+        Ok(Self {
+            path: path.to_string(),
+            watcher,
+            tiles: load_tileset(path)?,
+        })
+    }
+
+    // Tiles that changed (old, new) if the file was modified. They can be replaced in the
+    // world, so that only them are sent again to the clients.
+    pub fn reload_if_changed(&mut self, dt: &Duration) -> Vec<(Tile, Tile)> {
+        if self.watcher.poll(dt).is_empty() {
+            return Vec::new();
+        }
+
+        let tiles = match load_tileset(&self.path) {
+            Ok(tiles) => tiles,
+            Err(e) => {
+                // Probably still being written, the next change will be reloaded
+                println!("Cannot reload {}: {}", self.path, e);
+                return Vec::new();
+            }
+        };
+
+        println!("reloaded {}", self.path);
+
+        let changed_tiles = self
+            .tiles
+            .iter()
+            .zip(&tiles)
+            .filter(|(old_tile, new_tile)| old_tile != new_tile)
+            .map(|(old_tile, new_tile)| (old_tile.clone(), new_tile.clone()))
+            .collect();
+
+        self.tiles = tiles;
+
+        changed_tiles
+    }
+}
+
+// Frames are split in 8x8 tiles, row by row, to be shown by as many sprites
+#[derive(Clone)]
+pub struct AnimationFrame {
+    pub tiles: Vec<Tile>,
+    pub columns: usize,
+    pub duration: Duration,
+}
+
+// Order in which the frames of an animation are played, as in Aseprite
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    #[default]
+    Forward,
+    Reverse,
+    Pingpong,
+    PingpongReverse,
+}
+
+#[derive(Clone)]
+pub struct Animation {
+    pub frames: Vec<AnimationFrame>,
+    pub direction: Direction,
+}
+
+impl Animation {
+    // Indices of the frames in a full loop, ping-pong loops don't repeat their ends
+    fn sequence(&self) -> Vec<usize> {
+        let count = self.frames.len();
+
+        let forward: Vec<usize> = (0..count).collect();
+        let reverse: Vec<usize> = (0..count).rev().collect();
+
+        let back_and_forth = |first: &[usize], second: &[usize]| {
+            let mut sequence = first.to_vec();
+            if count > 2 {
+                sequence.extend_from_slice(&second[1..count - 1]);
+            }
+            sequence
+        };
+
+        match self.direction {
+            Direction::Forward => forward,
+            Direction::Reverse => reverse,
+            Direction::Pingpong => back_and_forth(&forward, &reverse),
+            Direction::PingpongReverse => back_and_forth(&reverse, &forward),
+        }
+    }
+
+    // Length of a full loop
+    fn duration(&self) -> Duration {
+        self.sequence()
+            .into_iter()
+            .map(|index| self.frames[index].duration)
+            .sum()
+    }
+
+    // Index of the frame to show after playing the animation for some time, looping
+    pub fn frame_index_at(&self, time: Duration) -> Option<usize> {
+        let sequence = self.sequence();
+        let loop_duration = self.duration();
+
+        if loop_duration.is_zero() {
+            return sequence.first().copied();
+        }
+
+        let mut time_in_loop =
+            Duration::from_nanos((time.as_nanos() % loop_duration.as_nanos()) as u64);
+
+        for index in sequence {
+            let frame = &self.frames[index];
+
+            if time_in_loop < frame.duration {
+                return Some(index);
+            }
+
+            time_in_loop -= frame.duration;
+        }
+
+        None
+    }
+}
+
+// Frames and tagged animations exported by Aseprite with a JSON array, eg.
+//
+// aseprite -b player.aseprite --sheet player.png --data player.json --format json-array --list-tags
+//
+// Each tag becomes an animation, the whole sheet is also available as "" if there's no tag.
+pub struct SpriteSheet {
+    pub animations: HashMap<String, Animation>,
+}
+
+#[derive(Deserialize)]
+struct AsepriteFile {
+    frames: Vec<AsepriteFrame>,
+    meta: AsepriteMeta,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AsepriteFrame {
+    frame: AsepriteRect,
+    #[serde(default)]
+    rotated: bool,
+    #[serde(default)]
+    trimmed: bool,
+    sprite_source_size: Option<AsepriteRect>,
+    source_size: Option<AsepriteSize>,
+    duration: u64, // ms
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AsepriteMeta {
+    image: String,
+    #[serde(default)]
+    frame_tags: Vec<AsepriteTag>,
+}
+
+#[derive(Deserialize)]
+struct AsepriteRect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Deserialize)]
+struct AsepriteSize {
+    w: u32,
+    h: u32,
+}
+
+#[derive(Deserialize)]
+struct AsepriteTag {
+    name: String,
+    from: usize,
+    to: usize,
+    #[serde(default)]
+    direction: Direction,
+}
+
+impl SpriteSheet {
+    pub fn load_aseprite(path: &str) -> Result<Self, String> {
+        let json_string = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let file: AsepriteFile = serde_json::from_str(&json_string).map_err(|e| {
+            format!("{path}: {e}, the frames must be exported as an array (--format json-array)")
+        })?;
+
+        // The sheet's path is relative to the JSON file
+        let image_path = Path::new(path)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(&file.meta.image);
+        let image = image::open(&image_path).map_err(|e| format!("{image_path:?}: {e}"))?;
+
+        let frames = file
+            .frames
+            .iter()
+            .map(|frame| aseprite_frame(&image, frame))
+            .collect::<Result<Vec<AnimationFrame>, String>>()?;
+
+        let mut animations = HashMap::new();
+
+        for tag in &file.meta.frame_tags {
+            if tag.from > tag.to || tag.to >= frames.len() {
+                return Err(format!("tag {} has unknown frames", tag.name));
+            }
+
+            animations.insert(
+                tag.name.clone(),
+                Animation {
+                    frames: frames[tag.from..=tag.to].to_vec(),
+                    direction: tag.direction,
+                },
+            );
+        }
+
+        if animations.is_empty() {
+            animations.insert(
+                String::new(),
+                Animation {
+                    frames,
+                    direction: Direction::Forward,
+                },
+            );
+        }
+
+        Ok(Self { animations })
+    }
+
+    pub fn animation(&self, name: &str) -> Option<&Animation> {
+        self.animations.get(name)
+    }
+}
+
+// Trimmed frames are put back in place on their full size, with transparent pixels around, up
+// to whole 8x8 tiles
+fn aseprite_frame(image: &DynamicImage, frame: &AsepriteFrame) -> Result<AnimationFrame, String> {
+    if frame.rotated {
+        return Err(String::from("rotated frames are not supported"));
+    }
+
+    let rect = &frame.frame;
+
+    if rect.x + rect.w > image.width() || rect.y + rect.h > image.height() {
+        return Err(format!(
+            "frame at ({}, {}) is out of the sheet",
+            rect.x, rect.y
+        ));
+    }
+
+    let (width, height, offset_x, offset_y) =
+        match (frame.trimmed, &frame.source_size, &frame.sprite_source_size) {
+            (true, Some(size), Some(source)) => (size.w, size.h, source.x, source.y),
+            _ => (rect.w, rect.h, 0, 0),
+        };
+
+    let (columns, rows) = (width.div_ceil(8), height.div_ceil(8));
+
+    if (columns * rows) as usize > MAX_SPRITES {
+        return Err(format!(
+            "frame of {width}x{height} needs more than {MAX_SPRITES} sprites"
+        ));
+    }
+
+    let pixel = |x: u32, y: u32| {
+        if x < offset_x || y < offset_y || x >= offset_x + rect.w || y >= offset_y + rect.h {
+            return TRANSPARENT;
+        }
+
+        let pixel = image.get_pixel(rect.x + x - offset_x, rect.y + y - offset_y);
+
+        Color::rgba(pixel[0], pixel[1], pixel[2], pixel[3])
+    };
+
+    let tiles = (0..columns * rows)
+        .map(|tile_index| {
+            let (tile_x, tile_y) = (tile_index % columns * 8, tile_index / columns * 8);
+
+            let pixels = (0..64)
+                .map(|index| pixel(tile_x + index % 8, tile_y + index / 8))
+                .collect();
+
+            Tile::from_pixels(8, 8, pixels)
+        })
+        .collect();
+
+    Ok(AnimationFrame {
+        tiles,
+        columns: columns as usize,
+        duration: Duration::from_millis(frame.duration),
+    })
+}
@@ -1,18 +1,53 @@
-#[derive(Debug, Clone, Copy, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
     pub b: u8,
+    pub a: u8,
 }
 
 impl Color {
     pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
-        Self { r, g, b }
+        Self { r, g, b, a: 0xFF }
+    }
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
+    // Pixels less than half opaque are not drawn at all, as the hardware only has fully
+    // transparent sprite pixels
+    pub fn is_transparent(&self) -> bool {
+        self.a < 0x80
+    }
+
+    // Opaque color of this one drawn over the background
+    pub fn over(&self, background: Color) -> Color {
+        let alpha = self.a as f32 / 255.0;
+        let blend = |front: u8, back: u8| {
+            (front as f32 * alpha + back as f32 * (1.0 - alpha)).round() as u8
+        };
+
+        Color::rgb(
+            blend(self.r, background.r),
+            blend(self.g, background.g),
+            blend(self.b, background.b),
+        )
+    }
+
+    // Relative luminance, 0-1
+    pub fn luminance(&self) -> f32 {
+        0.2126 * (self.r as f32 / 255.0)
+            + 0.7152 * (self.g as f32 / 255.0)
+            + 0.0722 * (self.b as f32 / 255.0)
     }
 }
 
+pub static TRANSPARENT: Color = Color::rgba(0x00, 0x00, 0x00, 0x00);
 pub static BLACK: Color = Color::rgb(0x00, 0x00, 0x00);
 pub static WHITE: Color = Color::rgb(0xFF, 0xFF, 0xFF);
+pub static LIGHT_GRAY: Color = Color::rgb(0xAA, 0xAA, 0xAA);
+pub static DARK_GRAY: Color = Color::rgb(0x55, 0x55, 0x55);
 pub static RED: Color = Color::rgb(0xFF, 0x00, 0x00);
 pub static GREEN: Color = Color::rgb(0x00, 0xFF, 0x00);
 pub static BLUE: Color = Color::rgb(0x00, 0x00, 0xFF);
@@ -0,0 +1,248 @@
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+use crate::ServerCommand;
+
+use super::color::{Color, BLACK, DARK_GRAY, LIGHT_GRAY, TRANSPARENT, WHITE};
+use super::tile::Tile;
+
+// The 4 shades of the DMG, from the lightest
+pub const SHADES: [Color; 4] = [WHITE, LIGHT_GRAY, DARK_GRAY, BLACK];
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dithering {
+    // Nearest shade
+    Threshold,
+    // 4x4 Bayer matrix, stable from frame to frame
+    Bayer,
+    FloydSteinberg,
+    // Only diffuses 3/4 of the error, for more contrast
+    Atkinson,
+}
+
+const BAYER_MATRIX: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+// (x offset, y offset, weight)
+const FLOYD_STEINBERG_DIFFUSION: [(i32, i32, f32); 4] = [
+    (1, 0, 7.0 / 16.0),
+    (-1, 1, 3.0 / 16.0),
+    (0, 1, 5.0 / 16.0),
+    (1, 1, 1.0 / 16.0),
+];
+
+const ATKINSON_DIFFUSION: [(i32, i32, f32); 6] = [
+    (1, 0, 1.0 / 8.0),
+    (2, 0, 1.0 / 8.0),
+    (-1, 1, 1.0 / 8.0),
+    (0, 1, 1.0 / 8.0),
+    (1, 1, 1.0 / 8.0),
+    (0, 2, 1.0 / 8.0),
+];
+
+// How colors are converted to the 4 shades
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conversion {
+    pub dithering: Dithering,
+    pub gamma: f32,
+    pub contrast: f32,
+
+    // Brightness (0-1) of each shade, from the lightest, decreasing
+    pub levels: [f32; 4],
+}
+
+impl Default for Conversion {
+    fn default() -> Self {
+        Self {
+            dithering: Dithering::Threshold,
+            gamma: 1.0,
+            contrast: 1.0,
+            levels: [1.0, 2.0 / 3.0, 1.0 / 3.0, 0.0],
+        }
+    }
+}
+
+impl Conversion {
+    // Conversion selected on the console, for the apps supporting it
+    pub fn from_command(command: &ServerCommand) -> Option<Self> {
+        if let ServerCommand::Dither {
+            method,
+            gamma,
+            contrast,
+            shades,
+        } = command
+        {
+            let mut conversion = Conversion {
+                dithering: *method,
+                gamma: gamma.max(0.01),
+                contrast: *contrast,
+                ..Default::default()
+            };
+
+            match shades.len() {
+                0 => {}
+                4 => conversion.levels.copy_from_slice(shades),
+                _ => println!("4 shades expected, using the default ones"),
+            }
+
+            Some(conversion)
+        } else {
+            None
+        }
+    }
+
+    // Brightness after tone mapping
+    fn tone(&self, color: Color) -> f32 {
+        let brightness = color
+            .over(WHITE)
+            .luminance()
+            .clamp(0.0, 1.0)
+            .powf(1.0 / self.gamma);
+
+        ((brightness - 0.5) * self.contrast + 0.5).clamp(0.0, 1.0)
+    }
+
+    // The darker and lighter shades around the brightness, and where it is between them (0-1)
+    fn bracket(&self, brightness: f32) -> (usize, usize, f32) {
+        for shade in 1..4 {
+            if brightness >= self.levels[shade] {
+                let (light, dark) = (self.levels[shade - 1], self.levels[shade]);
+                let position = if light > dark {
+                    (brightness - dark) / (light - dark)
+                } else {
+                    1.0
+                };
+
+                return (shade, shade - 1, position.clamp(0.0, 1.0));
+            }
+        }
+
+        (3, 3, 0.0)
+    }
+
+    fn nearest_shade(&self, brightness: f32) -> usize {
+        let (dark, light, position) = self.bracket(brightness);
+
+        if position >= 0.5 {
+            light
+        } else {
+            dark
+        }
+    }
+
+    // Shade index (0-3, from the lightest) of a single color, without dithering
+    pub fn shade_index(&self, color: Color) -> usize {
+        self.nearest_shade(self.tone(color))
+    }
+
+    // Shade indices of row-major pixels
+    pub fn shade_indices(&self, pixels: &[Color], width: usize, height: usize) -> Vec<usize> {
+        match self.dithering {
+            Dithering::Threshold => pixels
+                .iter()
+                .map(|color| self.shade_index(*color))
+                .collect(),
+            Dithering::Bayer => pixels
+                .iter()
+                .enumerate()
+                .map(|(index, color)| {
+                    let (x, y) = (index % width, index / width);
+                    let threshold = (BAYER_MATRIX[y % 4][x % 4] as f32 + 0.5) / 16.0;
+
+                    let (dark, light, position) = self.bracket(self.tone(*color));
+
+                    if position > threshold {
+                        light
+                    } else {
+                        dark
+                    }
+                })
+                .collect(),
+            Dithering::FloydSteinberg => {
+                self.diffuse_error(pixels, width, height, &FLOYD_STEINBERG_DIFFUSION)
+            }
+            Dithering::Atkinson => self.diffuse_error(pixels, width, height, &ATKINSON_DIFFUSION),
+        }
+    }
+
+    fn diffuse_error(
+        &self,
+        pixels: &[Color],
+        width: usize,
+        height: usize,
+        diffusion: &[(i32, i32, f32)],
+    ) -> Vec<usize> {
+        let mut brightness: Vec<f32> = pixels.iter().map(|color| self.tone(*color)).collect();
+        let mut shades = vec![0; pixels.len()];
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                let shade = self.nearest_shade(brightness[index]);
+                let error = brightness[index] - self.levels[shade];
+
+                shades[index] = shade;
+
+                for (dx, dy, weight) in diffusion {
+                    let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+
+                    if nx >= 0 && (nx as usize) < width && (ny as usize) < height {
+                        brightness[ny as usize * width + nx as usize] += error * weight;
+                    }
+                }
+            }
+        }
+
+        shades
+    }
+
+    // Transparent pixels stay transparent, the others are composited over white, the color of
+    // the empty screen
+    pub fn convert_pixels(&self, pixels: &[Color], width: usize, height: usize) -> Vec<Color> {
+        self.shade_indices(pixels, width, height)
+            .into_iter()
+            .zip(pixels)
+            .map(|(shade, color)| {
+                if color.is_transparent() {
+                    TRANSPARENT
+                } else {
+                    SHADES[shade]
+                }
+            })
+            .collect()
+    }
+
+    // Image with only the 4 shades, dithered as a whole
+    pub fn convert_image(&self, image: &DynamicImage) -> DynamicImage {
+        let (width, height) = image.dimensions();
+
+        let pixels: Vec<Color> = image
+            .to_rgba8()
+            .pixels()
+            .map(|pixel| Color::rgba(pixel[0], pixel[1], pixel[2], pixel[3]))
+            .collect();
+
+        let converted_pixels = self.convert_pixels(&pixels, width as usize, height as usize);
+
+        let converted_image = RgbaImage::from_fn(width, height, |x, y| {
+            let color = converted_pixels[(y * width + x) as usize];
+            Rgba([color.r, color.g, color.b, color.a])
+        });
+
+        DynamicImage::ImageRgba8(converted_image)
+    }
+
+    // Tiles already in the 4 shades are kept, eg. the ones sliced from converted images
+    pub fn convert_tile(&self, tile: &Tile) -> Tile {
+        let is_converted = tile
+            .pixels
+            .iter()
+            .all(|color| color.is_transparent() || SHADES.contains(color));
+
+        if is_converted {
+            return tile.clone();
+        }
+
+        let pixels = self.convert_pixels(&tile.pixels, tile.size.x as usize, tile.size.y as usize);
+
+        Tile::from_pixels(tile.size.x, tile.size.y, pixels)
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+
+use image::GenericImageView;
+
+use super::color::{BLACK, WHITE};
+use super::tile::Tile;
+
+pub struct Glyph {
+    pub width: u8,
+    pub pixels: Vec<bool>, // width * font height, true = ink
+}
+
+impl Glyph {
+    fn pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * self.width as usize + x]
+    }
+}
+
+pub struct Font {
+    height: u8,
+    spacing: u8, // Between glyphs
+    glyphs: HashMap<char, Glyph>,
+}
+
+// Monochrome bitmap of rendered text
+pub struct TextImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<bool>,
+}
+
+// Rendered text sliced into tiles, row by row
+pub struct TextTiles {
+    pub columns: usize,
+    pub rows: usize,
+    pub tiles: Vec<Tile>,
+}
+
+lazy_static! {
+    static ref DEFAULT_FONT: Arc<Font> = Arc::new(Font::builtin());
+}
+
+pub fn default_font() -> Arc<Font> {
+    DEFAULT_FONT.clone()
+}
+
+// 3x5 ASCII font, from ' ' to '~', one 3-bit row per value (MSB = left)
+const BUILTIN_GLYPHS: [[u8; 5]; 95] = [
+    [0, 0, 0, 0, 0], // ' '
+    [2, 2, 2, 0, 2], // !
+    [5, 5, 0, 0, 0], // "
+    [5, 7, 5, 7, 5], // #
+    [3, 6, 7, 3, 6], // $
+    [5, 1, 2, 4, 5], // %
+    [2, 5, 2, 5, 3], // &
+    [2, 2, 0, 0, 0], // '
+    [1, 2, 2, 2, 1], // (
+    [4, 2, 2, 2, 4], // )
+    [0, 5, 2, 5, 0], // *
+    [0, 2, 7, 2, 0], // +
+    [0, 0, 0, 2, 4], // ,
+    [0, 0, 7, 0, 0], // -
+    [0, 0, 0, 0, 2], // .
+    [1, 1, 2, 4, 4], // /
+    [7, 5, 5, 5, 7], // 0
+    [2, 6, 2, 2, 7], // 1
+    [7, 1, 7, 4, 7], // 2
+    [7, 1, 3, 1, 7], // 3
+    [5, 5, 7, 1, 1], // 4
+    [7, 4, 7, 1, 7], // 5
+    [7, 4, 7, 5, 7], // 6
+    [7, 1, 1, 1, 1], // 7
+    [7, 5, 7, 5, 7], // 8
+    [7, 5, 7, 1, 7], // 9
+    [0, 2, 0, 2, 0], // :
+    [0, 2, 0, 2, 4], // ;
+    [1, 2, 4, 2, 1], // <
+    [0, 7, 0, 7, 0], // =
+    [4, 2, 1, 2, 4], // >
+    [7, 1, 3, 0, 2], // ?
+    [2, 5, 7, 4, 3], // @
+    [2, 5, 7, 5, 5], // A
+    [6, 5, 6, 5, 6], // B
+    [3, 4, 4, 4, 3], // C
+    [6, 5, 5, 5, 6], // D
+    [7, 4, 6, 4, 7], // E
+    [7, 4, 6, 4, 4], // F
+    [3, 4, 5, 5, 3], // G
+    [5, 5, 7, 5, 5], // H
+    [7, 2, 2, 2, 7], // I
+    [1, 1, 1, 5, 2], // J
+    [5, 5, 6, 5, 5], // K
+    [4, 4, 4, 4, 7], // L
+    [5, 7, 7, 5, 5], // M
+    [6, 5, 5, 5, 5], // N
+    [2, 5, 5, 5, 2], // O
+    [6, 5, 6, 4, 4], // P
+    [2, 5, 5, 6, 3], // Q
+    [6, 5, 6, 5, 5], // R
+    [3, 4, 2, 1, 6], // S
+    [7, 2, 2, 2, 2], // T
+    [5, 5, 5, 5, 7], // U
+    [5, 5, 5, 5, 2], // V
+    [5, 5, 7, 7, 5], // W
+    [5, 5, 2, 5, 5], // X
+    [5, 5, 2, 2, 2], // Y
+    [7, 1, 2, 4, 7], // Z
+    [3, 2, 2, 2, 3], // [
+    [4, 4, 2, 1, 1], // \
+    [6, 2, 2, 2, 6], // ]
+    [2, 5, 0, 0, 0], // ^
+    [0, 0, 0, 0, 7], // _
+    [4, 2, 0, 0, 0], // `
+    [2, 5, 7, 5, 5], // a (same as uppercase)
+    [6, 5, 6, 5, 6], // b
+    [3, 4, 4, 4, 3], // c
+    [6, 5, 5, 5, 6], // d
+    [7, 4, 6, 4, 7], // e
+    [7, 4, 6, 4, 4], // f
+    [3, 4, 5, 5, 3], // g
+    [5, 5, 7, 5, 5], // h
+    [7, 2, 2, 2, 7], // i
+    [1, 1, 1, 5, 2], // j
+    [5, 5, 6, 5, 5], // k
+    [4, 4, 4, 4, 7], // l
+    [5, 7, 7, 5, 5], // m
+    [6, 5, 5, 5, 5], // n
+    [2, 5, 5, 5, 2], // o
+    [6, 5, 6, 4, 4], // p
+    [2, 5, 5, 6, 3], // q
+    [6, 5, 6, 5, 5], // r
+    [3, 4, 2, 1, 6], // s
+    [7, 2, 2, 2, 2], // t
+    [5, 5, 5, 5, 7], // u
+    [5, 5, 5, 5, 2], // v
+    [5, 5, 7, 7, 5], // w
+    [5, 5, 2, 5, 5], // x
+    [5, 5, 2, 2, 2], // y
+    [7, 1, 2, 4, 7], // z
+    [3, 2, 4, 2, 3], // {
+    [2, 2, 2, 2, 2], // |
+    [6, 2, 1, 2, 6], // }
+    [0, 3, 6, 0, 0], // ~
+];
+
+impl Font {
+    pub fn builtin() -> Self {
+        let mut font = Font {
+            height: 5,
+            spacing: 1,
+            glyphs: HashMap::new(),
+        };
+
+        for (index, rows) in BUILTIN_GLYPHS.iter().enumerate() {
+            let pixels = rows
+                .iter()
+                .flat_map(|row| (0..3).map(move |x| row & (0b100 >> x) != 0))
+                .collect();
+
+            font.add_glyph(char::from(32 + index as u8), 3, pixels);
+        }
+
+        font
+    }
+
+    // BDF font, or PNG grid of glyph cells for the printable ASCII characters
+    pub fn load(path: &str, cell_width: u8, cell_height: u8) -> Result<Self, String> {
+        if path.to_lowercase().ends_with(".bdf") {
+            Font::from_bdf(path)
+        } else {
+            let charset: String = (' '..='~').collect();
+            Font::from_png(path, cell_width, cell_height, &charset)
+        }
+        .map_err(|e| format!("{path}: {e}"))
+    }
+
+    // Grid of glyphs, in the order of the charset, dark pixels being ink
+    pub fn from_png(
+        path: &str,
+        cell_width: u8,
+        cell_height: u8,
+        charset: &str,
+    ) -> Result<Self, String> {
+        if cell_width == 0 || cell_height == 0 {
+            return Err(String::from("glyph size must not be 0"));
+        }
+
+        let image = image::open(path).map_err(|e| e.to_string())?;
+
+        let columns = image.width() / cell_width as u32;
+
+        if columns == 0 {
+            return Err(format!("image narrower than a {cell_width}px glyph"));
+        }
+
+        let mut font = Font {
+            height: cell_height,
+            spacing: 1,
+            glyphs: HashMap::new(),
+        };
+
+        for (index, char) in charset.chars().enumerate() {
+            let cell_x = (index as u32 % columns) * cell_width as u32;
+            let cell_y = (index as u32 / columns) * cell_height as u32;
+
+            if cell_y + cell_height as u32 > image.height() {
+                return Err(format!("no glyph for {char:?} in the image"));
+            }
+
+            let mut pixels = Vec::with_capacity(cell_width as usize * cell_height as usize);
+
+            for y in 0..cell_height as u32 {
+                for x in 0..cell_width as u32 {
+                    let pixel = image.get_pixel(cell_x + x, cell_y + y);
+                    let luminance = (pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32) / 3;
+
+                    pixels.push(pixel[3] >= 128 && luminance < 128);
+                }
+            }
+
+            font.add_glyph(char, cell_width, pixels);
+        }
+
+        Ok(font)
+    }
+
+    // Glyph Bitmap Distribution Format
+    pub fn from_bdf(path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+
+        let mut font = Font {
+            height: 0,
+            spacing: 0, // Included in the glyphs' advance
+            glyphs: HashMap::new(),
+        };
+
+        let mut font_descent = 0i32;
+
+        let mut encoding: Option<u32> = None;
+        let mut advance = 0i32;
+        let mut bbx = (0i32, 0i32, 0i32, 0i32);
+        let mut bitmap: Option<Vec<(u32, u32)>> = None; // Row bits, bit count
+
+        let parse = |value: Option<&str>| -> Result<i32, String> {
+            value
+                .ok_or_else(|| String::from("missing value"))?
+                .parse::<i32>()
+                .map_err(|e| e.to_string())
+        };
+
+        for line in content.lines() {
+            let mut items = line.split_whitespace();
+
+            match (items.next(), &mut bitmap) {
+                (Some("FONTBOUNDINGBOX"), _) => {
+                    let _width = parse(items.next())?;
+                    font.height = parse(items.next())? as u8;
+                    let _x_offset = parse(items.next())?;
+                    font_descent = -parse(items.next())?;
+                }
+                (Some("ENCODING"), _) => {
+                    encoding = u32::try_from(parse(items.next())?).ok();
+                }
+                (Some("DWIDTH"), _) => {
+                    advance = parse(items.next())?;
+                }
+                (Some("BBX"), _) => {
+                    bbx = (
+                        parse(items.next())?,
+                        parse(items.next())?,
+                        parse(items.next())?,
+                        parse(items.next())?,
+                    );
+                }
+                (Some("BITMAP"), _) => {
+                    bitmap = Some(Vec::new());
+                }
+                (Some("ENDCHAR"), Some(rows)) => {
+                    // Place the glyph's bounding box relatively to the font's baseline
+
+                    let (bbx_width, bbx_height, bbx_x, bbx_y) = bbx;
+                    let baseline = font.height as i32 - font_descent;
+                    let top = baseline - bbx_height - bbx_y;
+
+                    let width = advance.max(0) as usize;
+                    let mut pixels = vec![false; width * font.height as usize];
+
+                    for (row_index, (row, row_bits)) in rows.iter().enumerate() {
+                        let y = top + row_index as i32;
+
+                        for column in 0..bbx_width {
+                            let x = bbx_x + column;
+
+                            if x < 0 || x >= width as i32 || y < 0 || y >= font.height as i32 {
+                                continue;
+                            }
+
+                            if column as u32 >= *row_bits {
+                                break;
+                            }
+
+                            if row & (1 << (row_bits - 1 - column as u32)) != 0 {
+                                pixels[y as usize * width + x as usize] = true;
+                            }
+                        }
+                    }
+
+                    if let Some(char) = encoding.and_then(char::from_u32) {
+                        font.glyphs.insert(
+                            char,
+                            Glyph {
+                                width: width as u8,
+                                pixels,
+                            },
+                        );
+                    }
+
+                    bitmap = None;
+                    encoding = None;
+                }
+                (Some(hex), Some(rows)) => {
+                    let row = u32::from_str_radix(hex, 16).map_err(|e| e.to_string())?;
+                    rows.push((row, hex.len() as u32 * 4));
+                }
+                _ => {}
+            }
+        }
+
+        if font.height == 0 {
+            return Err(String::from("no FONTBOUNDINGBOX"));
+        }
+
+        Ok(font)
+    }
+
+    // Trim the empty columns around the glyph for variable-width text
+    fn add_glyph(&mut self, char: char, width: u8, pixels: Vec<bool>) {
+        let column_empty =
+            |x: usize| (0..self.height as usize).all(|y| !pixels[y * width as usize + x]);
+
+        let first = (0..width as usize).find(|x| !column_empty(*x));
+        let last = (0..width as usize).rev().find(|x| !column_empty(*x));
+
+        let glyph = match (first, last) {
+            (Some(first), Some(last)) => {
+                let trimmed_width = last - first + 1;
+
+                Glyph {
+                    width: trimmed_width as u8,
+                    pixels: (0..self.height as usize)
+                        .flat_map(|y| {
+                            pixels[y * width as usize + first..=y * width as usize + last].to_vec()
+                        })
+                        .collect(),
+                }
+            }
+
+            // Blank glyphs (eg. space) keep some width
+            _ => {
+                let blank_width = (width / 2).max(1);
+
+                Glyph {
+                    width: blank_width,
+                    pixels: vec![false; blank_width as usize * self.height as usize],
+                }
+            }
+        };
+
+        self.glyphs.insert(char, glyph);
+    }
+
+    // Unknown characters fall back to their uppercase version, then to '?'
+    fn glyph(&self, char: char) -> Option<&Glyph> {
+        self.glyphs
+            .get(&char)
+            .or_else(|| {
+                char.to_uppercase()
+                    .next()
+                    .and_then(|upper| self.glyphs.get(&upper))
+            })
+            .or_else(|| self.glyphs.get(&'?'))
+    }
+
+    pub fn text_width(&self, text: &str) -> usize {
+        let glyphs: Vec<&Glyph> = text.chars().filter_map(|char| self.glyph(char)).collect();
+
+        glyphs
+            .iter()
+            .map(|glyph| glyph.width as usize)
+            .sum::<usize>()
+            + glyphs.len().saturating_sub(1) * self.spacing as usize
+    }
+
+    pub fn rasterize(&self, text: &str) -> TextImage {
+        let width = self.text_width(text);
+        let height = self.height as usize;
+
+        let mut pixels = vec![false; width * height];
+        let mut pen_x = 0;
+
+        for glyph in text.chars().filter_map(|char| self.glyph(char)) {
+            for y in 0..height {
+                for x in 0..glyph.width as usize {
+                    if glyph.pixel(x, y) {
+                        pixels[y * width + pen_x + x] = true;
+                    }
+                }
+            }
+
+            pen_x += glyph.width as usize + self.spacing as usize;
+        }
+
+        TextImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+impl TextImage {
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * self.width + x]
+    }
+
+    // Slice the text into tiles, shifted by a pixel offset (0-7) within the first tile
+    pub fn to_tiles(&self, offset_x: usize, offset_y: usize) -> TextTiles {
+        let columns = (offset_x + self.width).div_ceil(8);
+        let rows = (offset_y + self.height).div_ceil(8);
+
+        let mut tiles = Vec::with_capacity(columns * rows);
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let pixels = (0..64)
+                    .map(|index| {
+                        let x = (column * 8 + index % 8) as isize - offset_x as isize;
+                        let y = (row * 8 + index / 8) as isize - offset_y as isize;
+
+                        let ink = x >= 0
+                            && y >= 0
+                            && (x as usize) < self.width
+                            && (y as usize) < self.height
+                            && self.pixel(x as usize, y as usize);
+
+                        if ink {
+                            BLACK
+                        } else {
+                            WHITE
+                        }
+                    })
+                    .collect();
+
+                tiles.push(Tile::from_pixels(8, 8, pixels));
+            }
+        }
+
+        TextTiles {
+            columns,
+            rows,
+            tiles,
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage, Luma};
+use parry2d::math::{Point, Vector};
+
+use crate::clients::client::Client;
+use crate::clients::screen::{Rotation, Screen};
+
+// Markers fill the LCD with a grid of 9x9 cells: a white margin separating them from the
+// device's casing, a black ring, then 5x5 bits
+const GRID_CELLS: usize = 9;
+const BITS_SIZE: usize = 5;
+
+// Bits of the 5x5 grid (row * 5 + column) used to find the orientation: top-left and the one
+// on its right are white, the one below and the other corners are black
+const WHITE_ORIENTATION_BITS: [usize; 2] = [0, 1];
+const BLACK_ORIENTATION_BITS: [usize; 4] = [4, 5, 20, 24];
+
+// The ID, then its complement to reject false detections
+const DATA_BITS: [usize; 16] = [2, 3, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19];
+
+const MAX_PHOTO_SIZE: u32 = 1600;
+const MIN_MARKER_PIXELS: usize = 200;
+
+pub struct DetectedMarker {
+    pub id: u8,
+    pub rotation: Rotation,
+    pub mirrored: bool,
+
+    // LCD corners in the photo, in pixels
+    pub corners: [Point<f32>; 4],
+}
+
+// true for white
+fn marker_bits(id: u8) -> [bool; BITS_SIZE * BITS_SIZE] {
+    let mut bits = [false; BITS_SIZE * BITS_SIZE];
+
+    for bit in WHITE_ORIENTATION_BITS {
+        bits[bit] = true;
+    }
+
+    for (i, bit) in DATA_BITS.iter().enumerate() {
+        bits[*bit] = (id >> (i % 8)) & 1 == (i < 8) as u8;
+    }
+
+    bits
+}
+
+fn decode_bits(bits: &[bool; BITS_SIZE * BITS_SIZE]) -> Option<u8> {
+    let oriented = WHITE_ORIENTATION_BITS.iter().all(|bit| bits[*bit])
+        && BLACK_ORIENTATION_BITS.iter().all(|bit| !bits[*bit]);
+
+    if !oriented {
+        return None;
+    }
+
+    let mut id = 0u8;
+
+    for (i, bit) in DATA_BITS.iter().enumerate() {
+        if i < 8 {
+            id |= (bits[*bit] as u8) << i;
+        } else if bits[*bit] == (id >> (i - 8) & 1 == 1) {
+            return None;
+        }
+    }
+
+    Some(id)
+}
+
+// The marker of a client, as displayed by the device
+pub fn marker_image(id: u8, res: Vector<usize>) -> DynamicImage {
+    let bits = marker_bits(id);
+
+    let image = GrayImage::from_fn(res.x as u32, res.y as u32, |x, y| {
+        let column = x as usize * GRID_CELLS / res.x;
+        let row = y as usize * GRID_CELLS / res.y;
+
+        let white = if column == 0 || row == 0 || column == GRID_CELLS - 1 || row == GRID_CELLS - 1
+        {
+            true
+        } else if column == 1 || row == 1 || column == GRID_CELLS - 2 || row == GRID_CELLS - 2 {
+            false
+        } else {
+            bits[(row - 2) * BITS_SIZE + (column - 2)]
+        };
+
+        Luma([if white { 0xFF } else { 0x00 }])
+    });
+
+    DynamicImage::ImageLuma8(image)
+}
+
+// Find the markers in a photo of the wall, taken from the front
+pub fn detect_markers(photo: &DynamicImage) -> Vec<DetectedMarker> {
+    let scale = (MAX_PHOTO_SIZE as f32 / photo.width().max(photo.height()) as f32).min(1.0);
+
+    let gray = if scale < 1.0 {
+        photo
+            .resize(MAX_PHOTO_SIZE, MAX_PHOTO_SIZE, FilterType::Triangle)
+            .to_luma8()
+    } else {
+        photo.to_luma8()
+    };
+
+    let threshold = otsu_threshold(&gray);
+    let (width, height) = (gray.width() as usize, gray.height() as usize);
+
+    let is_dark = |x: usize, y: usize| gray.get_pixel(x as u32, y as u32)[0] < threshold;
+
+    // Flood fill the dark areas, looking for the black rings
+
+    let mut visited = vec![false; width * height];
+    let mut markers = Vec::new();
+
+    for start_y in 0..height {
+        for start_x in 0..width {
+            if visited[start_y * width + start_x] || !is_dark(start_x, start_y) {
+                continue;
+            }
+
+            let mut extremes = QuadExtremes::new(start_x, start_y);
+            let mut stack = vec![(start_x, start_y)];
+            visited[start_y * width + start_x] = true;
+
+            while let Some((x, y)) = stack.pop() {
+                extremes.add(x, y);
+
+                let neighbors = [
+                    (x.wrapping_sub(1), y),
+                    (x + 1, y),
+                    (x, y.wrapping_sub(1)),
+                    (x, y + 1),
+                ];
+
+                for (nx, ny) in neighbors {
+                    if nx < width && ny < height && !visited[ny * width + nx] && is_dark(nx, ny) {
+                        visited[ny * width + nx] = true;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+
+            if extremes.count < MIN_MARKER_PIXELS {
+                continue;
+            }
+
+            if let Some(mut marker) = read_marker(&gray, threshold, &extremes.corners()) {
+                // Back to the photo's resolution
+                for corner in marker.corners.iter_mut() {
+                    *corner = Point::from(corner.coords / scale);
+                }
+
+                markers.push(marker);
+            }
+        }
+    }
+
+    markers
+}
+
+// Read the bits inside a black ring, trying every orientation
+fn read_marker(gray: &GrayImage, threshold: u8, ring: &[Point<f32>; 4]) -> Option<DetectedMarker> {
+    // The ring covers the 7 inner cells of the grid
+    let ring_cells = (GRID_CELLS - 2) as f32;
+
+    let mut photo_bits = [false; BITS_SIZE * BITS_SIZE];
+
+    for (i, bit) in photo_bits.iter_mut().enumerate() {
+        let u = (1.0 + (i % BITS_SIZE) as f32 + 0.5) / ring_cells;
+        let v = (1.0 + (i / BITS_SIZE) as f32 + 0.5) / ring_cells;
+
+        *bit = sample(gray, &bilinear(ring, u, v)) >= threshold as f32;
+    }
+
+    for rotation in [
+        Rotation::Deg0,
+        Rotation::Deg90,
+        Rotation::Deg180,
+        Rotation::Deg270,
+    ] {
+        for mirrored in [false, true] {
+            // Where the bits seen in the photo are on the device
+
+            let mut screen = Screen::new(Vector::new(1.0, 1.0), Vector::new(BITS_SIZE, BITS_SIZE));
+            screen.rotation = rotation;
+            screen.mirrored = mirrored;
+
+            let mut device_bits = [false; BITS_SIZE * BITS_SIZE];
+
+            for (i, bit) in photo_bits.iter().enumerate() {
+                let device_pos = screen.to_pixels(&Point::new(
+                    ((i % BITS_SIZE) as f32 + 0.5) / BITS_SIZE as f32,
+                    ((i / BITS_SIZE) as f32 + 0.5) / BITS_SIZE as f32,
+                ));
+
+                device_bits[device_pos.y as usize * BITS_SIZE + device_pos.x as usize] = *bit;
+            }
+
+            if let Some(id) = decode_bits(&device_bits) {
+                // Extend the ring to the whole LCD
+                let margin = 1.0 / ring_cells;
+
+                return Some(DetectedMarker {
+                    id,
+                    rotation,
+                    mirrored,
+                    corners: [
+                        bilinear(ring, -margin, -margin),
+                        bilinear(ring, 1.0 + margin, -margin),
+                        bilinear(ring, 1.0 + margin, 1.0 + margin),
+                        bilinear(ring, -margin, 1.0 + margin),
+                    ],
+                });
+            }
+        }
+    }
+
+    None
+}
+
+// Position the clients as in the photo, returning how many were found
+pub fn apply_layout(markers: &[DetectedMarker], clients: &mut [Client]) -> usize {
+    // Photo bounding box of each found client's LCD
+
+    let found: Vec<(usize, &DetectedMarker, Point<f32>, Vector<f32>)> = markers
+        .iter()
+        .filter_map(|marker| {
+            let index = clients.iter().position(|client| client.id() == marker.id)?;
+
+            let mins = marker
+                .corners
+                .iter()
+                .fold(Point::new(f32::MAX, f32::MAX), |mins, corner| {
+                    Point::new(mins.x.min(corner.x), mins.y.min(corner.y))
+                });
+            let maxs = marker
+                .corners
+                .iter()
+                .fold(Point::new(f32::MIN, f32::MIN), |maxs, corner| {
+                    Point::new(maxs.x.max(corner.x), maxs.y.max(corner.y))
+                });
+
+            Some((index, marker, mins, maxs - mins))
+        })
+        .collect();
+
+    if found.is_empty() {
+        return 0;
+    }
+
+    // World units per photo pixel, from the known size of the LCDs
+
+    let mut scales: Vec<f32> = found
+        .iter()
+        .map(|(index, marker, _, photo_size)| {
+            let size = clients[*index].screen().size;
+
+            let world_size = match marker.rotation {
+                Rotation::Deg90 | Rotation::Deg270 => Vector::new(size.y, size.x),
+                _ => size,
+            };
+
+            (world_size.x / photo_size.x + world_size.y / photo_size.y) / 2.0
+        })
+        .collect();
+
+    scales.sort_by(|a, b| a.total_cmp(b));
+    let scale = scales[scales.len() / 2];
+
+    // The top-left screen at the world origin
+
+    let origin = found
+        .iter()
+        .fold(Point::new(f32::MAX, f32::MAX), |origin, (_, _, mins, _)| {
+            Point::new(origin.x.min(mins.x), origin.y.min(mins.y))
+        });
+
+    for (index, marker, mins, photo_size) in found.iter() {
+        let client = &mut clients[*index];
+
+        let pos = (mins - origin) * scale;
+        let world_size = photo_size * scale;
+
+        let size = match marker.rotation {
+            Rotation::Deg90 | Rotation::Deg270 => Vector::new(world_size.y, world_size.x),
+            _ => world_size,
+        };
+
+        println!(
+            "client {}: found at {:.2} {:.2}, size {:.2} {:.2}, rotation {}, mirrored {}",
+            marker.id,
+            pos.x,
+            pos.y,
+            size.x,
+            size.y,
+            marker.rotation.degrees(),
+            marker.mirrored
+        );
+
+        client.set_screen_orientation(marker.rotation, marker.mirrored);
+        client.set_screen_size(size.x, size.y);
+        client.set_screen_position(pos.x, pos.y);
+    }
+
+    found.len()
+}
+
+// Corners of a dark area, assuming it's a roughly axis-aligned quadrilateral
+struct QuadExtremes {
+    count: usize,
+    top_left: (i64, usize, usize),     // min x + y
+    top_right: (i64, usize, usize),    // max x - y
+    bottom_right: (i64, usize, usize), // max x + y
+    bottom_left: (i64, usize, usize),  // min x - y
+}
+
+impl QuadExtremes {
+    fn new(x: usize, y: usize) -> Self {
+        let sum = x as i64 + y as i64;
+        let difference = x as i64 - y as i64;
+
+        Self {
+            count: 0,
+            top_left: (sum, x, y),
+            top_right: (difference, x, y),
+            bottom_right: (sum, x, y),
+            bottom_left: (difference, x, y),
+        }
+    }
+
+    fn add(&mut self, x: usize, y: usize) {
+        let sum = x as i64 + y as i64;
+        let difference = x as i64 - y as i64;
+
+        self.count += 1;
+
+        if sum < self.top_left.0 {
+            self.top_left = (sum, x, y);
+        }
+        if difference > self.top_right.0 {
+            self.top_right = (difference, x, y);
+        }
+        if sum > self.bottom_right.0 {
+            self.bottom_right = (sum, x, y);
+        }
+        if difference < self.bottom_left.0 {
+            self.bottom_left = (difference, x, y);
+        }
+    }
+
+    // Outer corners of the pixels, clockwise from the top-left
+    fn corners(&self) -> [Point<f32>; 4] {
+        [
+            Point::new(self.top_left.1 as f32, self.top_left.2 as f32),
+            Point::new(self.top_right.1 as f32 + 1.0, self.top_right.2 as f32),
+            Point::new(
+                self.bottom_right.1 as f32 + 1.0,
+                self.bottom_right.2 as f32 + 1.0,
+            ),
+            Point::new(self.bottom_left.1 as f32, self.bottom_left.2 as f32 + 1.0),
+        ]
+    }
+}
+
+// Point of a quadrilateral (clockwise from the top-left) at normalized coordinates
+fn bilinear(quad: &[Point<f32>; 4], u: f32, v: f32) -> Point<f32> {
+    let top = quad[0].coords * (1.0 - u) + quad[1].coords * u;
+    let bottom = quad[3].coords * (1.0 - u) + quad[2].coords * u;
+
+    Point::from(top * (1.0 - v) + bottom * v)
+}
+
+// Average of the 3x3 pixels around the point
+fn sample(gray: &GrayImage, pos: &Point<f32>) -> f32 {
+    let mut total = 0.0;
+    let mut count = 0.0;
+
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let x = pos.x as i64 + dx;
+            let y = pos.y as i64 + dy;
+
+            if x >= 0 && y >= 0 && (x as u32) < gray.width() && (y as u32) < gray.height() {
+                total += gray.get_pixel(x as u32, y as u32)[0] as f32;
+                count += 1.0;
+            }
+        }
+    }
+
+    if count > 0.0 {
+        total / count
+    } else {
+        0.0
+    }
+}
+
+// Threshold separating the dark and light pixels, maximizing the variance between them
+fn otsu_threshold(gray: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+
+    for pixel in gray.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+
+    let total = gray.pixels().len() as f64;
+    let total_sum: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(value, count)| value as f64 * *count as f64)
+        .sum();
+
+    let mut dark_count = 0.0;
+    let mut dark_sum = 0.0;
+    let mut best = (0.0, 128u8);
+
+    for (value, count) in histogram.iter().enumerate() {
+        dark_count += *count as f64;
+        dark_sum += value as f64 * *count as f64;
+
+        let light_count = total - dark_count;
+
+        if dark_count == 0.0 || light_count == 0.0 {
+            continue;
+        }
+
+        let dark_mean = dark_sum / dark_count;
+        let light_mean = (total_sum - dark_sum) / light_count;
+        let variance = dark_count * light_count * (dark_mean - light_mean).powi(2);
+
+        if variance > best.0 {
+            best = (variance, value as u8 + 1);
+        }
+    }
+
+    best.1
+}
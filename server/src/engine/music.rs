@@ -0,0 +1,355 @@
+use std::fs;
+use std::time::Duration;
+
+use log::info;
+use serde::Deserialize;
+
+use crate::clients::client::Client;
+
+use super::sound::{Duty, Envelope, Sound, Sweep, WaveVolume};
+
+// Tracker-style song, loaded from JSON:
+//
+// {
+//   "bpm": 120,
+//   "rows_per_beat": 4,
+//   "instruments": [{ "duty": "half", "volume": 12, "fade": 2 }, {}, { "wave": [...] }, { "shift": 2 }],
+//   "patterns": [[["C-4", "...", "OFF"], ["E-3"], [], ["C-5"]]],
+//   "order": [0, 0]
+// }
+//
+// Each pattern has one column of rows per channel (1-4), "..." or missing rows do nothing.
+// The wave channel only has 4 volume levels, its instrument's volume picks the nearest one.
+#[derive(Deserialize, Debug)]
+pub struct Song {
+    pub bpm: f32,
+    #[serde(default = "default_rows_per_beat")]
+    pub rows_per_beat: u32,
+    #[serde(default)]
+    pub instruments: [Instrument; 4],
+    pub patterns: Vec<[Vec<String>; 4]>,
+    pub order: Vec<usize>,
+    // Computed from the tempo once loaded
+    #[serde(skip)]
+    row_duration: Duration,
+}
+
+fn default_rows_per_beat() -> u32 {
+    4
+}
+
+// How a channel plays its notes
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Instrument {
+    pub duty: DutyName,
+    pub volume: u8,
+    pub fade: u8,
+    pub sweep: u8,
+    pub wave: Option<[u8; 32]>,
+    pub shift: u8,
+    pub short: bool,
+}
+
+impl Default for Instrument {
+    fn default() -> Self {
+        Self {
+            duty: DutyName::Half,
+            volume: 12,
+            fade: 0,
+            sweep: 0,
+            wave: None,
+            shift: 4,
+            short: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum DutyName {
+    Eighth,
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+impl From<DutyName> for Duty {
+    fn from(duty: DutyName) -> Self {
+        match duty {
+            DutyName::Eighth => Duty::Eighth,
+            DutyName::Quarter => Duty::Quarter,
+            DutyName::Half => Duty::Half,
+            DutyName::ThreeQuarters => Duty::ThreeQuarters,
+        }
+    }
+}
+
+// Triangle-ish default wave for the wave channel
+const DEFAULT_WAVE: [u8; 32] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, //
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+];
+
+// Songs faster than the server updates slow down instead of sending every row at once
+const MAX_ROWS_PER_UPDATE: usize = 4;
+
+#[derive(Debug, PartialEq)]
+enum Cell {
+    Note(f32), // Hz
+    Off,
+    Empty,
+}
+
+impl Song {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json_string = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut song: Song = serde_json::from_str(&json_string).map_err(|e| e.to_string())?;
+
+        if song.bpm <= 0.0 {
+            return Err(String::from("bpm must be positive"));
+        }
+
+        if song.rows_per_beat == 0 {
+            return Err(String::from("rows_per_beat must be positive"));
+        }
+
+        // Rows too short to be timed would never let the player catch up
+        song.row_duration =
+            Duration::try_from_secs_f32(60.0 / (song.bpm * song.rows_per_beat as f32))
+                .ok()
+                .filter(|row_duration| !row_duration.is_zero())
+                .ok_or_else(|| String::from("the tempo is too fast"))?;
+
+        if let Some(index) = song
+            .order
+            .iter()
+            .find(|index| **index >= song.patterns.len())
+        {
+            return Err(format!("unknown pattern {index}"));
+        }
+
+        Ok(song)
+    }
+
+    fn pattern_length(&self, pattern_index: usize) -> usize {
+        self.patterns[pattern_index]
+            .iter()
+            .map(|rows| rows.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn cell(&self, pattern_index: usize, channel: usize, row: usize) -> Cell {
+        match self.patterns[pattern_index][channel].get(row) {
+            Some(text) => parse_cell(text),
+            None => Cell::Empty,
+        }
+    }
+
+    fn sound(&self, channel: usize, frequency: f32) -> Sound {
+        let instrument = &self.instruments[channel];
+        let envelope = if instrument.fade > 0 {
+            Envelope::fade_out(instrument.volume, instrument.fade)
+        } else {
+            Envelope::constant(instrument.volume)
+        };
+
+        match channel {
+            0 => Sound::SquareSweep {
+                sweep: if instrument.sweep > 0 {
+                    Sweep {
+                        pace: instrument.sweep,
+                        decrease: true,
+                        step: 2,
+                    }
+                } else {
+                    Sweep::NONE
+                },
+                duty: instrument.duty.into(),
+                envelope,
+                frequency,
+                length: None,
+            },
+            1 => Sound::Square {
+                duty: instrument.duty.into(),
+                envelope,
+                frequency,
+                length: None,
+            },
+            2 => Sound::Wave {
+                samples: instrument.wave.unwrap_or(DEFAULT_WAVE),
+                volume: WaveVolume::nearest(instrument.volume),
+                frequency,
+                length: None,
+            },
+            _ => Sound::Noise {
+                envelope,
+                clock_shift: instrument.shift,
+                short: instrument.short,
+                divider: 0,
+                length: None,
+            },
+        }
+    }
+}
+
+// "C-4", "C#4", "OFF", "..."
+fn parse_cell(text: &str) -> Cell {
+    let text = text.trim();
+
+    if text.eq_ignore_ascii_case("off") || text == "===" {
+        return Cell::Off;
+    }
+
+    let chars: Vec<char> = text.chars().collect();
+
+    if chars.len() != 3 {
+        return Cell::Empty;
+    }
+
+    let semitone = match chars[0].to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return Cell::Empty,
+    };
+
+    let accidental = match chars[1] {
+        '#' => 1,
+        'b' => -1,
+        _ => 0,
+    };
+
+    let octave = match chars[2].to_digit(10) {
+        Some(octave) => octave as i32,
+        None => return Cell::Empty,
+    };
+
+    let midi_note = (octave + 1) * 12 + semitone + accidental;
+
+    Cell::Note(440.0 * 2f32.powf((midi_note - 69) as f32 / 12.0))
+}
+
+// Which clients play the song
+pub enum MusicOutput {
+    // Every channel on every client
+    All,
+
+    // Channels split across the given clients, round-robin
+    Clients(Vec<u8>),
+}
+
+impl MusicOutput {
+    fn plays_channel(&self, client_id: u8, channel: usize) -> bool {
+        match self {
+            MusicOutput::All => true,
+            MusicOutput::Clients(ids) => !ids.is_empty() && ids[channel % ids.len()] == client_id,
+        }
+    }
+}
+
+pub struct MusicPlayer {
+    song: Song,
+    output: MusicOutput,
+
+    order_index: usize,
+    row: usize,
+    time_since_last_row: Duration,
+}
+
+impl MusicPlayer {
+    pub fn new(song: Song, output: MusicOutput) -> Self {
+        // Start with the first row right away
+        let time_since_last_row = song.row_duration;
+
+        Self {
+            song,
+            output,
+            order_index: 0,
+            row: 0,
+            time_since_last_row,
+        }
+    }
+
+    // Play the rows due since the last update, looping at the end of the song
+    pub fn update(&mut self, dt: &Duration, clients: &mut [Client]) {
+        if self.song.order.is_empty() {
+            return;
+        }
+
+        self.time_since_last_row += *dt;
+
+        let row_duration = self.song.row_duration;
+
+        let mut rows_played = 0;
+
+        while self.time_since_last_row >= row_duration {
+            if rows_played == MAX_ROWS_PER_UPDATE {
+                self.time_since_last_row = Duration::ZERO;
+                break;
+            }
+
+            self.play_row(clients);
+            self.advance();
+            rows_played += 1;
+
+            self.time_since_last_row -= row_duration;
+        }
+    }
+
+    pub fn stop(&self, clients: &mut [Client]) {
+        for client in clients.iter_mut() {
+            for channel in 0..4 {
+                if self.output.plays_channel(client.id(), channel) {
+                    client.stop_sound(channel as u8 + 1);
+                }
+            }
+        }
+    }
+
+    fn play_row(&self, clients: &mut [Client]) {
+        let pattern_index = self.song.order[self.order_index];
+
+        for channel in 0..4 {
+            let cell = self.song.cell(pattern_index, channel, self.row);
+
+            if cell == Cell::Empty {
+                continue;
+            }
+
+            for client in clients.iter_mut() {
+                if !self.output.plays_channel(client.id(), channel) {
+                    continue;
+                }
+
+                match cell {
+                    Cell::Note(frequency) => {
+                        client.play_sound(&self.song.sound(channel, frequency))
+                    }
+                    Cell::Off => client.stop_sound(channel as u8 + 1),
+                    Cell::Empty => {}
+                }
+            }
+        }
+    }
+
+    fn advance(&mut self) {
+        self.row += 1;
+
+        let pattern_index = self.song.order[self.order_index];
+
+        if self.row >= self.song.pattern_length(pattern_index) {
+            self.row = 0;
+            self.order_index = (self.order_index + 1) % self.song.order.len();
+
+            if self.order_index == 0 {
+                info!("song looping");
+            }
+        }
+    }
+}
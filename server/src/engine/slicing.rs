@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+use parry2d::bounding_volume::{BoundingVolume, AABB};
+
+use crate::clients::client::Client;
+use crate::clients::screen::Screen;
+
+use super::color::Color;
+use super::tile::Tile;
+
+// The physical wall, bezels included, so that spanning content is hidden behind them
+pub fn wall_area(clients: &[Client]) -> AABB {
+    let mut area = AABB::new_invalid();
+
+    for client in clients.iter() {
+        area.merge(&client.screen().footprint());
+    }
+
+    area
+}
+
+// Part of the image (x, y, w, h) shown by the screen when the image covers the whole area,
+// keeping its ratio
+pub fn screen_crop(area: &AABB, screen: &Screen, image_size: (u32, u32)) -> (u32, u32, u32, u32) {
+    let screen_x_normalized = (screen.pos.x - area.mins.x) / area.extents().x;
+    let screen_y_normalized = (screen.pos.y - area.mins.y) / area.extents().y;
+
+    let screen_w_normalized = screen.world_size().x / area.extents().x;
+    let screen_h_normalized = screen.world_size().y / area.extents().y;
+
+    let area_ratio = area.extents().x / area.extents().y;
+    let image_ratio = image_size.0 as f32 / image_size.1 as f32;
+    let relative_ratio = area_ratio / image_ratio;
+
+    let (x_scale, y_scale) = if area_ratio > image_ratio {
+        (1.0, relative_ratio)
+    } else {
+        (relative_ratio, 1.0)
+    };
+
+    (
+        (screen_x_normalized * x_scale * image_size.0 as f32) as u32,
+        (screen_y_normalized * y_scale * image_size.1 as f32) as u32,
+        (screen_w_normalized * x_scale * image_size.0 as f32) as u32,
+        (screen_h_normalized * y_scale * image_size.1 as f32) as u32,
+    )
+}
+
+// Crop the screen's part of the image and resize it to the screen's resolution, as displayed
+// by the device
+pub fn screen_image(image: &DynamicImage, area: &AABB, screen: &Screen) -> DynamicImage {
+    let (x, y, w, h) = screen_crop(area, screen, image.dimensions());
+    let world_res = screen.world_res();
+
+    let screen_image = image.crop_imm(x, y, w.max(1), h.max(1)).resize_exact(
+        world_res.x as u32,
+        world_res.y as u32,
+        FilterType::Triangle,
+    );
+
+    screen.orient_image(&screen_image)
+}
+
+// Slice the image into 8x8 tiles, row by row, keeping the alpha channel
+pub fn image_to_tiles(image: &DynamicImage) -> (usize, Vec<Tile>) {
+    let columns = image.width() as usize / 8;
+    let rows = image.height() as usize / 8;
+
+    let mut tiles = Vec::with_capacity(columns * rows);
+
+    for row in 0..rows {
+        for column in 0..columns {
+            let pixels = (0..64)
+                .map(|index| {
+                    let pixel = image.get_pixel(
+                        (column * 8 + index % 8) as u32,
+                        (row * 8 + index / 8) as u32,
+                    );
+
+                    Color::rgba(pixel[0], pixel[1], pixel[2], pixel[3])
+                })
+                .collect();
+
+            tiles.push(Tile::from_pixels(8, 8, pixels));
+        }
+    }
+
+    (columns, tiles)
+}
+
+// Tiles displayed by each client, to only send the ones that changed
+pub struct ScreenTiles {
+    client_tiles: HashMap<u8, Vec<Tile>>,
+}
+
+impl ScreenTiles {
+    pub fn new() -> Self {
+        Self {
+            client_tiles: HashMap::new(),
+        }
+    }
+
+    pub fn contains(&self, client_id: u8) -> bool {
+        self.client_tiles.contains_key(&client_id)
+    }
+
+    // The client's screen was reset, its tiles are drawn again if it comes back
+    pub fn remove(&mut self, client_id: u8) {
+        self.client_tiles.remove(&client_id);
+    }
+
+    // New clients, and the ones showing approximate tiles that could now be exact
+    pub fn needs_redraw(&self, client: &Client) -> bool {
+        !self.contains(client.id()) || client.has_approximate_tiles()
+    }
+
+    // Draw the screen-sized tiles, in runs of changed tiles per row. Cells showing the closest
+    // tile, as the tiles memory was full, are redrawn too.
+    pub fn draw(&mut self, client: &mut Client, columns: usize, tiles: Vec<Tile>) {
+        let previous_tiles = self
+            .client_tiles
+            .get(&client.id())
+            .filter(|previous_tiles| previous_tiles.len() == tiles.len());
+
+        let changed: Vec<bool> = tiles
+            .iter()
+            .enumerate()
+            .map(|(index, tile)| {
+                let is_exact = client
+                    .is_background_tile_exact((index % columns) as u8, (index / columns) as u8);
+
+                match previous_tiles {
+                    Some(previous_tiles) => !is_exact || previous_tiles[index] != *tile,
+                    None => true,
+                }
+            })
+            .collect();
+
+        for (row_index, row) in tiles.chunks(columns).enumerate() {
+            let changed = |column: usize| changed[row_index * columns + column];
+
+            let mut column = 0;
+
+            while column < row.len() {
+                if !changed(column) {
+                    column += 1;
+                    continue;
+                }
+
+                let run_start = column;
+
+                while column < row.len() && changed(column) {
+                    column += 1;
+                }
+
+                client.draw_tiles(
+                    &row[run_start..column],
+                    column - run_start,
+                    run_start as i32,
+                    row_index as i32,
+                );
+            }
+        }
+
+        self.client_tiles.insert(client.id(), tiles);
+    }
+}
@@ -0,0 +1,227 @@
+use parry2d::math::Point;
+
+use crate::clients::client::Client;
+
+// Volume envelope, applied by the APU over time
+#[derive(Debug, Clone, Copy)]
+pub struct Envelope {
+    pub volume: u8, // 0-15
+    pub increase: bool,
+    pub pace: u8, // 0-7, 0 = constant volume
+}
+
+impl Envelope {
+    pub const fn constant(volume: u8) -> Self {
+        Self {
+            volume,
+            increase: false,
+            pace: 0,
+        }
+    }
+
+    pub const fn fade_out(volume: u8, pace: u8) -> Self {
+        Self {
+            volume,
+            increase: false,
+            pace,
+        }
+    }
+
+    fn to_register(self) -> u8 {
+        (self.volume.min(15) << 4) | ((self.increase as u8) << 3) | self.pace.min(7)
+    }
+}
+
+// Frequency sweep of the first square channel
+#[derive(Debug, Clone, Copy)]
+pub struct Sweep {
+    pub pace: u8, // 0-7, 0 = no sweep
+    pub decrease: bool,
+    pub step: u8, // 0-7
+}
+
+impl Sweep {
+    pub const NONE: Sweep = Sweep {
+        pace: 0,
+        decrease: false,
+        step: 0,
+    };
+
+    fn to_register(self) -> u8 {
+        (self.pace.min(7) << 4) | ((self.decrease as u8) << 3) | self.step.min(7)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Duty {
+    Eighth,
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum WaveVolume {
+    Mute,
+    Full,
+    Half,
+    Quarter,
+}
+
+impl WaveVolume {
+    // Closest level to a 0-15 volume, as used by the envelopes of the other channels
+    pub fn nearest(volume: u8) -> Self {
+        match volume.min(15) {
+            0..=1 => WaveVolume::Mute,
+            2..=5 => WaveVolume::Quarter,
+            6..=11 => WaveVolume::Half,
+            _ => WaveVolume::Full,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Sound {
+    // Channel 1
+    SquareSweep {
+        sweep: Sweep,
+        duty: Duty,
+        envelope: Envelope,
+        frequency: f32,     // Hz
+        length: Option<u8>, // 1-64 ticks of 1/256s, None = until stopped
+    },
+
+    // Channel 2
+    Square {
+        duty: Duty,
+        envelope: Envelope,
+        frequency: f32,
+        length: Option<u8>,
+    },
+
+    // Channel 3, playing the 32 4-bit samples of the wave RAM
+    Wave {
+        samples: [u8; 32],
+        volume: WaveVolume,
+        frequency: f32,
+        length: Option<u16>, // 1-256
+    },
+
+    // Channel 4
+    Noise {
+        envelope: Envelope,
+        clock_shift: u8, // 0-15
+        short: bool,     // 7-bit LFSR, more metallic
+        divider: u8,     // 0-7
+        length: Option<u8>,
+    },
+}
+
+impl Sound {
+    pub fn channel(&self) -> u8 {
+        match self {
+            Sound::SquareSweep { .. } => 1,
+            Sound::Square { .. } => 2,
+            Sound::Wave { .. } => 3,
+            Sound::Noise { .. } => 4,
+        }
+    }
+
+    // Values of the channel's NRx0-NRx4 registers, the last one triggering the sound
+    pub fn registers(&self) -> [u8; 5] {
+        match self {
+            Sound::SquareSweep {
+                sweep,
+                duty,
+                envelope,
+                frequency,
+                length,
+            } => {
+                let mut registers = square_registers(*duty, *envelope, *frequency, *length);
+                registers[0] = sweep.to_register();
+                registers
+            }
+            Sound::Square {
+                duty,
+                envelope,
+                frequency,
+                length,
+            } => square_registers(*duty, *envelope, *frequency, *length),
+            Sound::Wave {
+                volume,
+                frequency,
+                length,
+                ..
+            } => {
+                let period = frequency_to_period(*frequency, 65536.0);
+
+                [
+                    0x80, // DAC on
+                    (256 - length.unwrap_or(256).clamp(1, 256)) as u8,
+                    (*volume as u8) << 5,
+                    period as u8,
+                    trigger_register(length.is_some(), period),
+                ]
+            }
+            Sound::Noise {
+                envelope,
+                clock_shift,
+                short,
+                divider,
+                length,
+            } => [
+                0,
+                64 - length.unwrap_or(64).clamp(1, 64),
+                envelope.to_register(),
+                (clock_shift.min(&15) << 4) | ((*short as u8) << 3) | divider.min(&7),
+                trigger_register(length.is_some(), 0),
+            ],
+        }
+    }
+
+    // Wave RAM content, two samples per byte
+    pub fn wave_ram(&self) -> Option<[u8; 16]> {
+        match self {
+            Sound::Wave { samples, .. } => {
+                let mut data = [0u8; 16];
+
+                for (i, byte) in data.iter_mut().enumerate() {
+                    *byte = (samples[i * 2].min(15) << 4) | samples[i * 2 + 1].min(15);
+                }
+
+                Some(data)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn square_registers(duty: Duty, envelope: Envelope, frequency: f32, length: Option<u8>) -> [u8; 5] {
+    let period = frequency_to_period(frequency, 131072.0);
+
+    [
+        0,
+        ((duty as u8) << 6) | (64 - length.unwrap_or(64).clamp(1, 64)),
+        envelope.to_register(),
+        period as u8,
+        trigger_register(length.is_some(), period),
+    ]
+}
+
+fn trigger_register(length_enabled: bool, period: u16) -> u8 {
+    0x80 | ((length_enabled as u8) << 6) | ((period >> 8) as u8 & 0b111)
+}
+
+// The APU frequency is base / (2048 - period), with an 11-bit period
+fn frequency_to_period(frequency: f32, base: f32) -> u16 {
+    (2048.0 - base / frequency.max(1.0)).clamp(0.0, 2047.0) as u16
+}
+
+// Play the sound on the screens displaying the given world position
+pub fn play_sound_at(clients: &mut [Client], pos: &Point<f32>, sound: &Sound) {
+    for client in clients.iter_mut() {
+        if client.screen().contains(pos) {
+            client.play_sound(sound);
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+use parry2d::math::Point;
+
+use super::font::Font;
+
+pub struct Text {
+    pub text: String,
+    pub font: Arc<Font>,
+    pub pos: Point<f32>, // Top-left
+}
+
+impl Text {
+    pub fn new(text: &str, font: Arc<Font>) -> Self {
+        Self {
+            text: String::from(text),
+            font,
+            pos: Point::new(0.0, 0.0),
+        }
+    }
+
+    pub fn set_position(&mut self, x: f32, y: f32) {
+        self.pos.x = x;
+        self.pos.y = y;
+    }
+}
@@ -2,7 +2,7 @@ use parry2d::na::Vector2;
 
 use super::color::Color;
 
-#[derive(Clone, Hash)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Tile {
     pub size: Vector2<u8>,
     pub pixels: Vec<Color>,
@@ -0,0 +1,734 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use parry2d::math::{Point, Vector};
+use serde::Deserialize;
+
+use super::assets;
+use super::tile::Tile;
+use super::world::World;
+
+// Flags in the high bits of the tile IDs
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+const GID_MASK: u32 = 0x0FFF_FFFF;
+
+// Custom properties, as text
+pub type Properties = HashMap<String, String>;
+
+// Map made with the Tiled editor, saved as TMX or JSON, with its tilesets embedded or in
+// separate TSX/JSON files. The map tiles must be multiples of 8 pixels, they are split in
+// background tiles.
+pub struct TiledMap {
+    pub columns: u32,
+    pub rows: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub properties: Properties,
+    pub layers: Vec<TileLayer>,
+    pub objects: Vec<MapObject>,
+    tilesets: Vec<Tileset>,
+}
+
+pub struct TileLayer {
+    pub name: String,
+    pub visible: bool,
+    pub properties: Properties,
+
+    // Row-major tile IDs, 0 for no tile
+    pub gids: Vec<u32>,
+}
+
+// Spawn point, area or tile from an object layer, in map pixels
+pub struct MapObject {
+    pub name: String,
+    pub kind: String,
+    pub layer: String,
+    pub visible: bool,
+    pub properties: Properties,
+
+    pub pos: Point<f32>, // Top left
+    pub size: Vector<f32>,
+    pub gid: Option<u32>,
+}
+
+struct Tileset {
+    first_gid: u32,
+    tiles: Vec<Tile>,
+}
+
+// Where a tileset's tiles are in its image
+struct TilesetImage {
+    path: PathBuf,
+    tile_width: u32,
+    tile_height: u32,
+    margin: u32,
+    spacing: u32,
+    columns: Option<u32>,
+    count: Option<u32>,
+}
+
+impl TiledMap {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+
+        let map = if path.ends_with(".tmx") {
+            parse_tmx(&text, directory)
+        } else {
+            parse_json(&text, directory)
+        }
+        .map_err(|e| format!("{path}: {e}"))?;
+
+        if !map.tile_width.is_multiple_of(8) || !map.tile_height.is_multiple_of(8) {
+            return Err(format!(
+                "{path}: tiles of {}x{} are not made of 8x8 tiles",
+                map.tile_width, map.tile_height
+            ));
+        }
+
+        Ok(map)
+    }
+
+    // Tile of an ID, flipped as its flags say
+    pub fn tile(&self, gid: u32) -> Option<Tile> {
+        let id = gid & GID_MASK;
+
+        let tileset = self
+            .tilesets
+            .iter()
+            .filter(|tileset| tileset.first_gid <= id)
+            .max_by_key(|tileset| tileset.first_gid)?;
+
+        let tile = tileset.tiles.get((id - tileset.first_gid) as usize)?;
+
+        Some(flip_tile(
+            tile,
+            gid & FLIPPED_HORIZONTALLY != 0,
+            gid & FLIPPED_VERTICALLY != 0,
+            gid & FLIPPED_DIAGONALLY != 0,
+        ))
+    }
+
+    // World position of a map position, 8 map pixels being a background cell
+    pub fn world_position(&self, world: &World, pos: &Point<f32>) -> Point<f32> {
+        let cell_size = world.background_cell_size();
+
+        Point::new(pos.x / 8.0 * cell_size.x, pos.y / 8.0 * cell_size.y)
+    }
+
+    // Draw the visible tile layers on the background from the world origin, the upper layers
+    // over the lower ones, and create sprites for every visible tile object, one per 8x8 tile.
+    // The world must already fit the client screens, to know the size of the cells.
+    //
+    // Returns the sprites of each object, row by row, none for the objects left to the app.
+    pub fn add_to_world(&self, world: &mut World) -> Result<Vec<(Vec<usize>, &MapObject)>, String> {
+        let cells_per_tile = (self.tile_width / 8, self.tile_height / 8);
+        let mut background: HashMap<Point<i32>, Tile> = HashMap::new();
+
+        for layer in self.layers.iter().filter(|layer| layer.visible) {
+            for (index, gid) in layer.gids.iter().enumerate() {
+                let tile = match self.tile(*gid) {
+                    Some(tile) if *gid != 0 => tile,
+                    _ => continue,
+                };
+
+                // Tiles larger than the map's overflow on the right and bottom
+
+                let column = (index as u32 % self.columns * cells_per_tile.0) as i32;
+                let row = (index as u32 / self.columns * cells_per_tile.1) as i32;
+                let tile_columns = tile.size.x as usize / 8;
+
+                for (sub_index, sub_tile) in split_tile(&tile).into_iter().enumerate() {
+                    let cell = Point::new(
+                        column + (sub_index % tile_columns) as i32,
+                        row + (sub_index / tile_columns) as i32,
+                    );
+
+                    let sub_tile = match background.get(&cell) {
+                        Some(below) => stack_tiles(&sub_tile, below),
+                        None => sub_tile,
+                    };
+
+                    background.insert(cell, sub_tile);
+                }
+            }
+        }
+
+        for (cell, tile) in background {
+            world.set_background_tile(cell, &tile);
+        }
+
+        let mut sprites = Vec::new();
+
+        for object in self.objects.iter() {
+            let tile = match object.gid.and_then(|gid| self.tile(gid)) {
+                Some(tile) if object.visible => tile,
+                _ => {
+                    sprites.push((Vec::new(), object));
+                    continue;
+                }
+            };
+
+            let tile_columns = tile.size.x as usize / 8;
+            let mut sprite_ids = Vec::new();
+
+            for (sub_index, sub_tile) in split_tile(&tile).into_iter().enumerate() {
+                let offset = Vector::new(
+                    (sub_index % tile_columns * 8) as f32,
+                    (sub_index / tile_columns * 8) as f32,
+                );
+                let pos = self.world_position(world, &(object.pos + offset));
+                let sprite_id = world.create_sprite(&sub_tile)?;
+
+                world.move_sprite(sprite_id, pos.x, pos.y);
+                sprite_ids.push(sprite_id);
+            }
+
+            sprites.push((sprite_ids, object));
+        }
+
+        Ok(sprites)
+    }
+}
+
+// Diagonal flips are applied first, as in Tiled
+fn flip_tile(tile: &Tile, horizontally: bool, vertically: bool, diagonally: bool) -> Tile {
+    let (width, height) = (tile.size.x as usize, tile.size.y as usize);
+    let (flipped_width, flipped_height) = if diagonally {
+        (height, width)
+    } else {
+        (width, height)
+    };
+
+    let pixels = (0..flipped_width * flipped_height)
+        .map(|index| {
+            let mut x = index % flipped_width;
+            let mut y = index / flipped_width;
+
+            if horizontally {
+                x = flipped_width - 1 - x;
+            }
+            if vertically {
+                y = flipped_height - 1 - y;
+            }
+            if diagonally {
+                std::mem::swap(&mut x, &mut y);
+            }
+
+            tile.pixels[y * width + x]
+        })
+        .collect();
+
+    Tile::from_pixels(flipped_width as u8, flipped_height as u8, pixels)
+}
+
+// 8x8 tiles of a larger tile, row by row
+fn split_tile(tile: &Tile) -> Vec<Tile> {
+    let (width, height) = (tile.size.x as usize, tile.size.y as usize);
+    let mut tiles = Vec::new();
+
+    for tile_y in (0..height).step_by(8) {
+        for tile_x in (0..width).step_by(8) {
+            let pixels = (0..64)
+                .map(|index| tile.pixels[(tile_y + index / 8) * width + tile_x + index % 8])
+                .collect();
+
+            tiles.push(Tile::from_pixels(8, 8, pixels));
+        }
+    }
+
+    tiles
+}
+
+fn stack_tiles(above: &Tile, below: &Tile) -> Tile {
+    let pixels = above
+        .pixels
+        .iter()
+        .zip(&below.pixels)
+        .map(|(above, below)| {
+            if above.is_transparent() {
+                *below
+            } else if below.is_transparent() {
+                *above
+            } else {
+                above.over(*below)
+            }
+        })
+        .collect();
+
+    Tile::from_pixels(above.size.x, above.size.y, pixels)
+}
+
+impl TilesetImage {
+    fn load(&self, first_gid: u32) -> Result<Tileset, String> {
+        let image = image::open(&self.path).map_err(|e| format!("{:?}: {e}", self.path))?;
+
+        if !self.tile_width.is_multiple_of(8) || !self.tile_height.is_multiple_of(8) {
+            return Err(format!("tileset {:?} is not made of 8x8 tiles", self.path));
+        }
+
+        if self.tile_width > u8::MAX as u32 || self.tile_height > u8::MAX as u32 {
+            return Err(format!("tileset {:?} has too large tiles", self.path));
+        }
+
+        let fitting_columns = (image.width().saturating_sub(self.margin) + self.spacing)
+            / (self.tile_width + self.spacing).max(1);
+        let fitting_rows = (image.height().saturating_sub(self.margin) + self.spacing)
+            / (self.tile_height + self.spacing).max(1);
+
+        let columns = self.columns.unwrap_or(fitting_columns).min(fitting_columns);
+        let count = self
+            .count
+            .unwrap_or(columns * fitting_rows)
+            .min(columns * fitting_rows);
+
+        let tiles = (0..count)
+            .map(|index| {
+                assets::crop_tile(
+                    &image,
+                    self.margin + index % columns * (self.tile_width + self.spacing),
+                    self.margin + index / columns * (self.tile_height + self.spacing),
+                    self.tile_width as u8,
+                    self.tile_height as u8,
+                )
+            })
+            .collect();
+
+        Ok(Tileset { first_gid, tiles })
+    }
+}
+
+// Tileset in its own TSX or JSON file
+fn external_tileset_image(source: &str, directory: &Path) -> Result<TilesetImage, String> {
+    let path = directory.join(source);
+    let text = fs::read_to_string(&path).map_err(|e| format!("{path:?}: {e}"))?;
+    let tileset_directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+    if source.ends_with(".tsx") {
+        let document = roxmltree::Document::parse(&text).map_err(|e| e.to_string())?;
+
+        xml_tileset_image(&document.root_element(), tileset_directory)
+    } else {
+        json_tileset_image(&text, tileset_directory)
+    }
+}
+
+// Tile IDs of a layer, as CSV or base64 of little-endian u32, maybe compressed
+fn decode_gids(data: &str, encoding: &str, compression: &str) -> Result<Vec<u32>, String> {
+    match encoding {
+        "csv" => data
+            .split(',')
+            .map(|gid| gid.trim().parse::<u32>().map_err(|e| e.to_string()))
+            .collect(),
+        "base64" => {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(data.trim())
+                .map_err(|e| e.to_string())?;
+
+            let mut decompressed = Vec::new();
+
+            match compression {
+                "" => decompressed = bytes,
+                "zlib" => {
+                    ZlibDecoder::new(&bytes[..])
+                        .read_to_end(&mut decompressed)
+                        .map_err(|e| e.to_string())?;
+                }
+                "gzip" => {
+                    GzDecoder::new(&bytes[..])
+                        .read_to_end(&mut decompressed)
+                        .map_err(|e| e.to_string())?;
+                }
+                _ => return Err(format!("unsupported compression {compression}")),
+            }
+
+            Ok(decompressed
+                .chunks_exact(4)
+                .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect())
+        }
+        _ => Err(format!("unsupported encoding {encoding}")),
+    }
+}
+
+// TMX/TSX
+
+fn parse_tmx(text: &str, directory: &Path) -> Result<TiledMap, String> {
+    let document = roxmltree::Document::parse(text).map_err(|e| e.to_string())?;
+    let root = document.root_element();
+
+    if xml_attribute(&root, "infinite").unwrap_or(0) != 0 {
+        return Err(String::from("infinite maps are not supported"));
+    }
+
+    let mut map = TiledMap {
+        columns: xml_attribute(&root, "width").ok_or("no map width")?,
+        rows: xml_attribute(&root, "height").ok_or("no map height")?,
+        tile_width: xml_attribute(&root, "tilewidth").ok_or("no tile width")?,
+        tile_height: xml_attribute(&root, "tileheight").ok_or("no tile height")?,
+        properties: xml_properties(&root),
+        layers: Vec::new(),
+        objects: Vec::new(),
+        tilesets: Vec::new(),
+    };
+
+    for node in root.children().filter(|node| node.has_tag_name("tileset")) {
+        let first_gid = xml_attribute(&node, "firstgid").ok_or("no tileset first ID")?;
+
+        let tileset = match node.attribute("source") {
+            Some(source) => external_tileset_image(source, directory)?,
+            None => xml_tileset_image(&node, directory)?,
+        };
+
+        map.tilesets.push(tileset.load(first_gid)?);
+    }
+
+    xml_layers(&root, true, &mut map)?;
+
+    Ok(map)
+}
+
+// Layers in groups are flattened, hidden groups hide their layers
+fn xml_layers(
+    parent: &roxmltree::Node,
+    parent_visible: bool,
+    map: &mut TiledMap,
+) -> Result<(), String> {
+    for node in parent.children().filter(|node| node.is_element()) {
+        let name = node.attribute("name").unwrap_or("").to_string();
+        let visible = parent_visible && xml_attribute(&node, "visible").unwrap_or(1) != 0;
+
+        match node.tag_name().name() {
+            "layer" => {
+                let data = node
+                    .children()
+                    .find(|child| child.has_tag_name("data"))
+                    .ok_or_else(|| format!("layer {name} has no data"))?;
+
+                let gids = match data.attribute("encoding") {
+                    Some(encoding) => decode_gids(
+                        data.text().unwrap_or(""),
+                        encoding,
+                        data.attribute("compression").unwrap_or(""),
+                    )?,
+                    None => data
+                        .children()
+                        .filter(|child| child.has_tag_name("tile"))
+                        .map(|child| xml_attribute(&child, "gid").unwrap_or(0))
+                        .collect(),
+                };
+
+                map.layers.push(TileLayer {
+                    name,
+                    visible,
+                    properties: xml_properties(&node),
+                    gids,
+                });
+            }
+            "objectgroup" => {
+                for object in node.children().filter(|child| child.has_tag_name("object")) {
+                    let gid = xml_attribute(&object, "gid");
+                    let size = Vector::new(
+                        xml_attribute(&object, "width").unwrap_or(0.0),
+                        xml_attribute(&object, "height").unwrap_or(0.0),
+                    );
+
+                    map.objects.push(MapObject {
+                        name: object.attribute("name").unwrap_or("").to_string(),
+                        kind: object
+                            .attribute("type")
+                            .or_else(|| object.attribute("class"))
+                            .unwrap_or("")
+                            .to_string(),
+                        layer: name.clone(),
+                        visible: visible && xml_attribute(&object, "visible").unwrap_or(1) != 0,
+                        properties: xml_properties(&object),
+                        pos: object_top_left(
+                            xml_attribute(&object, "x").unwrap_or(0.0),
+                            xml_attribute(&object, "y").unwrap_or(0.0),
+                            &size,
+                            gid,
+                        ),
+                        size,
+                        gid,
+                    });
+                }
+            }
+            "group" => xml_layers(&node, visible, map)?,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn xml_tileset_image(node: &roxmltree::Node, directory: &Path) -> Result<TilesetImage, String> {
+    let image = node
+        .children()
+        .find(|child| child.has_tag_name("image"))
+        .ok_or("image collection tilesets are not supported")?;
+
+    Ok(TilesetImage {
+        path: directory.join(image.attribute("source").ok_or("no tileset image")?),
+        tile_width: xml_attribute(node, "tilewidth").ok_or("no tileset tile width")?,
+        tile_height: xml_attribute(node, "tileheight").ok_or("no tileset tile height")?,
+        margin: xml_attribute(node, "margin").unwrap_or(0),
+        spacing: xml_attribute(node, "spacing").unwrap_or(0),
+        columns: xml_attribute(node, "columns"),
+        count: xml_attribute(node, "tilecount"),
+    })
+}
+
+fn xml_attribute<T: std::str::FromStr>(node: &roxmltree::Node, name: &str) -> Option<T> {
+    node.attribute(name).and_then(|value| value.parse().ok())
+}
+
+// Multiline strings are in the text of the property
+fn xml_properties(node: &roxmltree::Node) -> Properties {
+    node.children()
+        .filter(|child| child.has_tag_name("properties"))
+        .flat_map(|properties| properties.children())
+        .filter(|property| property.has_tag_name("property"))
+        .filter_map(|property| {
+            let value = property.attribute("value").or_else(|| property.text())?;
+
+            Some((property.attribute("name")?.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+// Tile objects are placed by their bottom left corner
+fn object_top_left(x: f32, y: f32, size: &Vector<f32>, gid: Option<u32>) -> Point<f32> {
+    match gid {
+        Some(_) => Point::new(x, y - size.y),
+        None => Point::new(x, y),
+    }
+}
+
+// JSON
+
+#[derive(Deserialize)]
+struct JsonMap {
+    width: u32,
+    height: u32,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+    #[serde(default)]
+    tilesets: Vec<JsonTileset>,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+
+#[derive(Deserialize)]
+struct JsonLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default = "default_visible")]
+    visible: bool,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+
+    // Tile layers
+    data: Option<serde_json::Value>,
+    #[serde(default)]
+    encoding: String,
+    #[serde(default)]
+    compression: String,
+
+    // Object layers
+    #[serde(default)]
+    objects: Vec<JsonObject>,
+
+    // Groups
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonObject {
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type")]
+    kind: String,
+    #[serde(default)]
+    class: String,
+    #[serde(default = "default_visible")]
+    visible: bool,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    gid: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct JsonTileset {
+    firstgid: Option<u32>,
+    source: Option<String>,
+    image: Option<String>,
+    tilewidth: Option<u32>,
+    tileheight: Option<u32>,
+    #[serde(default)]
+    margin: u32,
+    #[serde(default)]
+    spacing: u32,
+    columns: Option<u32>,
+    tilecount: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct JsonProperty {
+    name: String,
+    value: serde_json::Value,
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+fn parse_json(text: &str, directory: &Path) -> Result<TiledMap, String> {
+    let json_map: JsonMap = serde_json::from_str(text).map_err(|e| e.to_string())?;
+
+    if json_map.infinite {
+        return Err(String::from("infinite maps are not supported"));
+    }
+
+    let mut map = TiledMap {
+        columns: json_map.width,
+        rows: json_map.height,
+        tile_width: json_map.tilewidth,
+        tile_height: json_map.tileheight,
+        properties: json_properties(&json_map.properties),
+        layers: Vec::new(),
+        objects: Vec::new(),
+        tilesets: Vec::new(),
+    };
+
+    for json_tileset in &json_map.tilesets {
+        let first_gid = json_tileset.firstgid.ok_or("no tileset first ID")?;
+
+        let tileset = match &json_tileset.source {
+            Some(source) => external_tileset_image(source, directory)?,
+            None => json_tileset_to_image(json_tileset, directory)?,
+        };
+
+        map.tilesets.push(tileset.load(first_gid)?);
+    }
+
+    json_layers(&json_map.layers, true, &mut map)?;
+
+    Ok(map)
+}
+
+fn json_layers(
+    layers: &[JsonLayer],
+    parent_visible: bool,
+    map: &mut TiledMap,
+) -> Result<(), String> {
+    for layer in layers {
+        let visible = parent_visible && layer.visible;
+
+        match layer.kind.as_str() {
+            "tilelayer" => {
+                let gids = match &layer.data {
+                    Some(serde_json::Value::Array(gids)) => gids
+                        .iter()
+                        .map(|gid| gid.as_u64().unwrap_or(0) as u32)
+                        .collect(),
+                    Some(serde_json::Value::String(data)) => {
+                        decode_gids(data, &layer.encoding, &layer.compression)?
+                    }
+                    _ => return Err(format!("layer {} has no data", layer.name)),
+                };
+
+                map.layers.push(TileLayer {
+                    name: layer.name.clone(),
+                    visible,
+                    properties: json_properties(&layer.properties),
+                    gids,
+                });
+            }
+            "objectgroup" => {
+                for object in &layer.objects {
+                    let size = Vector::new(object.width, object.height);
+
+                    map.objects.push(MapObject {
+                        name: object.name.clone(),
+                        kind: if object.kind.is_empty() {
+                            object.class.clone()
+                        } else {
+                            object.kind.clone()
+                        },
+                        layer: layer.name.clone(),
+                        visible: visible && object.visible,
+                        properties: json_properties(&object.properties),
+                        pos: object_top_left(object.x, object.y, &size, object.gid),
+                        size,
+                        gid: object.gid,
+                    });
+                }
+            }
+            "group" => json_layers(&layer.layers, visible, map)?,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn json_tileset_image(text: &str, directory: &Path) -> Result<TilesetImage, String> {
+    let json_tileset: JsonTileset = serde_json::from_str(text).map_err(|e| e.to_string())?;
+
+    json_tileset_to_image(&json_tileset, directory)
+}
+
+fn json_tileset_to_image(
+    json_tileset: &JsonTileset,
+    directory: &Path,
+) -> Result<TilesetImage, String> {
+    Ok(TilesetImage {
+        path: directory.join(
+            json_tileset
+                .image
+                .as_ref()
+                .ok_or("image collection tilesets are not supported")?,
+        ),
+        tile_width: json_tileset.tilewidth.ok_or("no tileset tile width")?,
+        tile_height: json_tileset.tileheight.ok_or("no tileset tile height")?,
+        margin: json_tileset.margin,
+        spacing: json_tileset.spacing,
+        columns: json_tileset.columns,
+        count: json_tileset.tilecount,
+    })
+}
+
+// Strings are kept as is, the other values as JSON
+fn json_properties(properties: &[JsonProperty]) -> Properties {
+    properties
+        .iter()
+        .map(|property| {
+            let value = match &property.value {
+                serde_json::Value::String(text) => text.clone(),
+                value => value.to_string(),
+            };
+
+            (property.name.clone(), value)
+        })
+        .collect()
+}
@@ -1,21 +1,45 @@
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::sync::Arc;
 
 use log::{error, info};
 use parry2d::{
     bounding_volume::{BoundingVolume, AABB},
-    math::Point,
+    math::{Point, Vector},
 };
 
 use crate::clients::client::Client;
 
-use super::{sprite::Sprite, tile::Tile};
+use super::{
+    color::WHITE,
+    font::{Font, TextImage},
+    sprite::Sprite,
+    text::Text,
+    tile::Tile,
+};
+
+// Hardware sprites of the Game Boy
+pub const MAX_SPRITES: usize = 40;
 
 pub struct World {
     area: AABB,
 
-    // TODO background?
+    // Background tiles, on a grid of cells starting at the world origin
+    background: HashMap<Point<i32>, Tile>,
+    background_cell_size: Option<Vector<f32>>,
+
+    // IDs are the hardware sprites of the clients, reused once deleted
     sprites: HashMap<usize, Sprite>,
-    next_sprite_id: usize,
+
+    // Sprites currently displayed by each client
+    visible_sprites: HashMap<u8, HashSet<usize>>,
+
+    // Texts, drawn as background tiles
+    texts: HashMap<usize, Text>,
+    next_text_id: usize,
+
+    // Tiles covered by each text on each client (client, text) -> (tile x, tile y, columns, rows),
+    // to erase the text when it changes
+    text_tile_rects: HashMap<(u8, usize), (i32, i32, usize, usize)>,
 
     events: Vec<Event>,
 }
@@ -24,26 +48,36 @@ impl World {
     pub fn new() -> Self {
         Self {
             area: AABB::new_invalid(),
+            background: HashMap::new(),
+            background_cell_size: None,
             sprites: HashMap::new(),
-            next_sprite_id: 0,
+            visible_sprites: HashMap::new(),
+            texts: HashMap::new(),
+            next_text_id: 0,
+            text_tile_rects: HashMap::new(),
             events: Vec::new(),
         }
     }
 
+    pub fn has_sprite(&self, id: usize) -> bool {
+        self.sprites.contains_key(&id)
+    }
+
     pub fn get_sprite(&mut self, id: usize) -> &Sprite {
         // TODO return Option? Result?
         self.sprites.get(&id).unwrap()
     }
 
-    pub fn create_sprite(&mut self, tile: &Tile) -> usize {
-        let id = self.next_sprite_id;
-        self.next_sprite_id = self.next_sprite_id + 1;
+    pub fn create_sprite(&mut self, tile: &Tile) -> Result<usize, String> {
+        let id = (0..MAX_SPRITES)
+            .find(|id| !self.sprites.contains_key(id))
+            .ok_or_else(|| format!("no sprite left, {MAX_SPRITES} at most"))?;
 
         self.sprites.insert(id, Sprite::new(tile));
 
         self.events.push(Event::SpriteCreated(id));
 
-        id
+        Ok(id)
     }
 
     pub fn move_sprite(&mut self, id: usize, x: f32, y: f32) {
@@ -56,6 +90,128 @@ impl World {
         }
     }
 
+    // Change the sprite's tile, eg. to the current frame of an animation
+    pub fn set_sprite_tile(&mut self, id: usize, tile: &Tile) {
+        match self.sprites.get_mut(&id) {
+            Some(sprite) => {
+                if sprite.tile != *tile {
+                    sprite.tile = tile.clone();
+                    self.events.push(Event::SpriteMoved(id));
+                }
+            }
+            None => error!("no sprite {id}"),
+        }
+    }
+
+    // Use a new version of a tile everywhere, eg. after reloading it
+    pub fn replace_tile(&mut self, old_tile: &Tile, new_tile: &Tile) {
+        for (cell, tile) in self.background.iter_mut() {
+            if tile == old_tile {
+                *tile = new_tile.clone();
+                self.events.push(Event::BackgroundChanged(*cell));
+            }
+        }
+
+        for (id, sprite) in self.sprites.iter_mut() {
+            if sprite.tile == *old_tile {
+                sprite.tile = new_tile.clone();
+                self.events.push(Event::SpriteMoved(*id));
+            }
+        }
+    }
+
+    pub fn delete_sprite(&mut self, id: usize) {
+        if self.sprites.remove(&id).is_some() {
+            self.events.push(Event::SpriteDeleted(id));
+        }
+    }
+
+    // Remove all the sprites and texts, and blank the background
+    pub fn clear(&mut self) {
+        let sprite_ids: Vec<usize> = self.sprites.keys().copied().collect();
+        for id in sprite_ids {
+            self.delete_sprite(id);
+        }
+
+        let text_ids: Vec<usize> = self.texts.keys().copied().collect();
+        for id in text_ids {
+            self.delete_text(id);
+        }
+
+        let cells: Vec<Point<i32>> = self.background.keys().copied().collect();
+        for cell in cells {
+            self.set_background_tile(cell, &EMPTY_TILE);
+        }
+    }
+
+    pub fn create_text(&mut self, text: &str, font: Arc<Font>) -> usize {
+        let id = self.next_text_id;
+        self.next_text_id += 1;
+
+        self.texts.insert(id, Text::new(text, font));
+
+        self.events.push(Event::TextChanged(id));
+
+        id
+    }
+
+    pub fn has_text(&self, id: usize) -> bool {
+        self.texts.contains_key(&id)
+    }
+
+    pub fn set_text(&mut self, id: usize, text: &str) {
+        match self.texts.get_mut(&id) {
+            Some(world_text) => {
+                world_text.text = String::from(text);
+                self.events.push(Event::TextChanged(id));
+            }
+            None => error!("no text {id}"),
+        }
+    }
+
+    pub fn move_text(&mut self, id: usize, x: f32, y: f32) {
+        match self.texts.get_mut(&id) {
+            Some(text) => {
+                text.set_position(x, y);
+                self.events.push(Event::TextChanged(id));
+            }
+            None => error!("no text {id}"),
+        }
+    }
+
+    pub fn delete_text(&mut self, id: usize) {
+        if self.texts.remove(&id).is_some() {
+            self.events.push(Event::TextChanged(id));
+        }
+    }
+
+    pub fn background_cell_size(&self) -> Vector<f32> {
+        self.background_cell_size.unwrap_or_else(Vector::zeros)
+    }
+
+    pub fn cell_at(&self, pos: &Point<f32>) -> Point<i32> {
+        let cell_size = self.background_cell_size();
+
+        Point::new(
+            (pos.x / cell_size.x).floor() as i32,
+            (pos.y / cell_size.y).floor() as i32,
+        )
+    }
+
+    pub fn cell_center(&self, cell: &Point<i32>) -> Point<f32> {
+        let cell_size = self.background_cell_size();
+
+        Point::new(
+            (cell.x as f32 + 0.5) * cell_size.x,
+            (cell.y as f32 + 0.5) * cell_size.y,
+        )
+    }
+
+    pub fn set_background_tile(&mut self, cell: Point<i32>, tile: &Tile) {
+        self.background.insert(cell, tile.clone());
+        self.events.push(Event::BackgroundChanged(cell));
+    }
+
     pub fn fit_client_screens(&mut self, clients: &Vec<Client>) -> &AABB {
         self.area = AABB::new_invalid();
 
@@ -63,43 +219,220 @@ impl World {
             self.area.merge(&client.screen().bounding_box());
         }
 
+        // Default to background cells the size of the first screen's tiles
+
+        if self.background_cell_size.is_none() && !clients.is_empty() {
+            self.background_cell_size = Some(clients[0].screen().tile_size());
+        }
+
         &self.area
     }
 
     pub fn sync_clients(&mut self, clients: &mut Vec<Client>) {
-        for event in self.events.iter() {
+        // Forget the clients that are gone, eg. moved to another zone, to send them everything
+        // again if they come back
+
+        let is_present = |client_id: u8| clients.iter().any(|client| client.id() == client_id);
+
+        self.visible_sprites
+            .retain(|client_id, _| is_present(*client_id));
+        self.text_tile_rects
+            .retain(|(client_id, _), _| is_present(*client_id));
+
+        // Send everything to the clients seen for the first time
+
+        for client in clients.iter_mut() {
+            if let Entry::Vacant(entry) = self.visible_sprites.entry(client.id()) {
+                entry.insert(HashSet::new());
+
+                let cells: Vec<Point<i32>> = self.background.keys().copied().collect();
+                for cell in cells {
+                    self.sync_background_cell(client, &cell);
+                }
+
+                let ids: Vec<usize> = self.sprites.keys().copied().collect();
+                for id in ids {
+                    self.sync_sprite(client, id);
+                }
+
+                let ids: Vec<usize> = self.texts.keys().copied().collect();
+                for id in ids {
+                    self.sync_text(client, id);
+                }
+            }
+        }
+
+        for event in std::mem::take(&mut self.events) {
             info!("World event: {:?}", event);
 
             match event {
-                Event::SpriteCreated(id) => {
+                Event::SpriteCreated(id) | Event::SpriteMoved(id) => {
                     for client in clients.iter_mut() {
-                        let sprite = self.sprites.get(id).unwrap(); // TODO err
-
-                        if client.screen().contains(&sprite.pos) {
-                            let screen_pos = to_client_space(client, &sprite.pos);
-
-                            client.draw_sprite(*id, sprite, screen_pos.x, screen_pos.y);
-                        }
+                        self.sync_sprite(client, id);
                     }
                 }
                 Event::SpriteDeleted(id) => {
-                    //todo!()
-                }
-                Event::SpriteMoved(id) => {
                     for client in clients.iter_mut() {
-                        let sprite = self.sprites.get(id).unwrap(); // TODO err
+                        let visible_sprites = self.visible_sprites.entry(client.id()).or_default();
 
-                        if client.screen().contains(&sprite.pos) {
-                            let screen_pos = to_client_space(client, &sprite.pos);
-
-                            client.draw_sprite(*id, sprite, screen_pos.x, screen_pos.y);
+                        if visible_sprites.remove(&id) {
+                            client.hide_sprite(id);
                         }
                     }
                 }
+                Event::BackgroundChanged(cell) => {
+                    for client in clients.iter_mut() {
+                        self.sync_background_cell(client, &cell);
+                    }
+                }
+                Event::TextChanged(id) => {
+                    for client in clients.iter_mut() {
+                        self.sync_text(client, id);
+                    }
+                }
             }
         }
+    }
+
+    // Put the background back where the text was, then draw the part of it that is on the
+    // client's screen
+    fn sync_text(&mut self, client: &mut Client, id: usize) {
+        if let Some((tile_x, tile_y, columns, rows)) =
+            self.text_tile_rects.remove(&(client.id(), id))
+        {
+            let tiles = self.background_tiles(client, tile_x, tile_y, columns, rows);
+            client.draw_tiles(&tiles, columns, tile_x, tile_y);
+        }
+
+        let text = match self.texts.get(&id) {
+            Some(text) => text,
+            None => return,
+        };
+
+        // Rasterized along the wall, then turned as the device displays it
+
+        let text_image = text.font.rasterize(&text.text);
+        let text_size = world_pixel_size(client, text_image.width, text_image.height);
+
+        let (width, height, pixels) =
+            client
+                .screen()
+                .orient_pixels(&text_image.pixels, text_image.width, text_image.height);
+
+        let text_image = TextImage {
+            width,
+            height,
+            pixels,
+        };
+
+        let pixel_pos = to_client_rect_pixels(client, &text.pos, &text_size);
+
+        let text_tiles = text_image.to_tiles(
+            pixel_pos.x.rem_euclid(8) as usize,
+            pixel_pos.y.rem_euclid(8) as usize,
+        );
+
+        let tile_x = pixel_pos.x.div_euclid(8);
+        let tile_y = pixel_pos.y.div_euclid(8);
+
+        let screen_columns = (client.screen().res.x / 8) as i32;
+        let screen_rows = (client.screen().res.y / 8) as i32;
+
+        let on_screen = tile_x < screen_columns
+            && tile_y < screen_rows
+            && tile_x + text_tiles.columns as i32 > 0
+            && tile_y + text_tiles.rows as i32 > 0;
+
+        if on_screen {
+            client.draw_tiles(&text_tiles.tiles, text_tiles.columns, tile_x, tile_y);
 
-        self.events.clear();
+            self.text_tile_rects.insert(
+                (client.id(), id),
+                (tile_x, tile_y, text_tiles.columns, text_tiles.rows),
+            );
+        }
+    }
+
+    // Background under a rectangle of the client's tiles, row by row, blank where there's none
+    fn background_tiles(
+        &self,
+        client: &Client,
+        tile_x: i32,
+        tile_y: i32,
+        columns: usize,
+        rows: usize,
+    ) -> Vec<Tile> {
+        let mut tiles = vec![EMPTY_TILE.clone(); columns * rows];
+
+        for (cell, tile) in self.background.iter() {
+            let center = self.cell_center(cell);
+
+            if !client.screen().contains(&center) {
+                continue;
+            }
+
+            let screen_pos = to_client_space(client, &center);
+            let (column, row) = (
+                (screen_pos.x / 8) as i32 - tile_x,
+                (screen_pos.y / 8) as i32 - tile_y,
+            );
+
+            if (0..columns as i32).contains(&column) && (0..rows as i32).contains(&row) {
+                tiles[row as usize * columns + column as usize] = client.screen().orient_tile(tile);
+            }
+        }
+
+        tiles
+    }
+
+    // Draw the cell's tile on the client if its center is on its screen
+    fn sync_background_cell(&self, client: &mut Client, cell: &Point<i32>) {
+        let center = self.cell_center(cell);
+
+        if let Some(tile) = self.background.get(cell) {
+            if client.screen().contains(&center) {
+                let screen_pos = to_client_space(client, &center);
+                let tile = client.screen().orient_tile(tile);
+
+                client.draw_tile(&tile, screen_pos.x, screen_pos.y);
+            }
+        }
+    }
+
+    // Draw the sprite on the client if it's on its screen, hide it otherwise
+    fn sync_sprite(&mut self, client: &mut Client, id: usize) {
+        // Deleted since it changed
+        let sprite = match self.sprites.get(&id) {
+            Some(sprite) => sprite,
+            None => return,
+        };
+
+        let visible_sprites = self.visible_sprites.entry(client.id()).or_default();
+
+        if client.screen().contains(&sprite.pos) {
+            let sprite_size = world_pixel_size(
+                client,
+                sprite.tile.size.x as usize,
+                sprite.tile.size.y as usize,
+            );
+            let screen_pos = to_client_rect_pixels(client, &sprite.pos, &sprite_size);
+
+            let oriented_sprite = Sprite {
+                tile: client.screen().orient_tile(&sprite.tile),
+                pos: sprite.pos,
+            };
+
+            client.draw_sprite(
+                id,
+                &oriented_sprite,
+                screen_pos.x.max(0) as u8,
+                screen_pos.y.max(0) as u8,
+            );
+
+            visible_sprites.insert(id);
+        } else if visible_sprites.remove(&id) {
+            client.hide_sprite(id);
+        }
     }
 }
 
@@ -108,13 +441,38 @@ enum Event {
     SpriteCreated(usize),
     SpriteDeleted(usize),
     SpriteMoved(usize),
+    BackgroundChanged(Point<i32>),
+    TextChanged(usize),
+}
+
+lazy_static! {
+    static ref EMPTY_TILE: Tile = Tile::filled(8, 8, WHITE);
 }
 
 fn to_client_space(client: &Client, world_pos: &Point<f32>) -> Point<u8> {
-    Point::new(
-        ((world_pos.x - client.screen().pos.x) / client.screen().size.x
-            * (client.screen().res.x as f32)) as u8,
-        ((world_pos.y - client.screen().pos.y) / client.screen().size.y
-            * (client.screen().res.y as f32)) as u8,
+    let pixels = client.screen().to_pixels(world_pos);
+
+    Point::new(pixels.x as u8, pixels.y as u8)
+}
+
+// Device pixel of the top-left of a world rectangle, unclamped to place objects partially
+// offscreen
+fn to_client_rect_pixels(
+    client: &Client,
+    world_pos: &Point<f32>,
+    world_size: &Vector<f32>,
+) -> Point<i32> {
+    let pixels = client.screen().rect_to_pixels(world_pos, world_size);
+
+    Point::new(pixels.x.floor() as i32, pixels.y.floor() as i32)
+}
+
+// World size of an image of the given pixel size, drawn at the client's density
+fn world_pixel_size(client: &Client, width: usize, height: usize) -> Vector<f32> {
+    let tile_size = client.screen().tile_size();
+
+    Vector::new(
+        width as f32 * tile_size.x / 8.0,
+        height as f32 * tile_size.y / 8.0,
     )
 }
@@ -1,12 +1,18 @@
 use std::{io, sync::mpsc, thread};
 
 use clap::Parser;
+use clients::screen::Rotation;
+use engine::dithering::Dithering;
+use transition::TransitionKind;
 
 mod apps;
 mod clients;
 mod commands;
 mod engine;
+mod playlist;
 mod server;
+mod transition;
+mod zone;
 
 #[macro_use]
 extern crate lazy_static;
@@ -21,15 +27,182 @@ struct Args {
 pub enum ServerCommand {
     // TODO alias subcommands?
     Quit,
-    Pos { client_id: u8, x: f32, y: f32 },
-    App { app: AppName },
+    Pos {
+        client_id: u8,
+        x: f32,
+        y: f32,
+    },
+    // Rotation of a screen as mounted, mirroring it horizontally if needed
+    Orient {
+        client_id: u8,
+        rotation: Rotation,
+        #[arg(long)]
+        mirrored: bool,
+    },
+    // Casing around the LCD, on each side of the device before rotation
+    Bezel {
+        client_id: u8,
+        left: f32,
+        top: f32,
+        right: f32,
+        bottom: f32,
+    },
+    // Switch to an app, with its arguments as name=value, or list the apps
+    App {
+        name: Option<String>,
+        arguments: Vec<String>,
+        #[arg(long)]
+        transition: Option<TransitionKind>,
+    },
+    Layout {
+        #[command(subcommand)]
+        command: LayoutCommand,
+    },
+    // Conversion to the 4 shades of the tiles and sprites, and of the current app's images
+    Dither {
+        method: Dithering,
+        #[arg(long, default_value_t = 1.0)]
+        gamma: f32,
+        #[arg(long, default_value_t = 1.0)]
+        contrast: f32,
+        // Brightness (0-1) of each shade, from the lightest
+        #[arg(long, value_delimiter = ',')]
+        shades: Vec<f32>,
+    },
+    Music {
+        #[command(subcommand)]
+        command: MusicCommand,
+    },
+    Playlist {
+        #[command(subcommand)]
+        command: PlaylistCommand,
+    },
+    // Screens running their own app, apart from the rest of the wall
+    Zone {
+        #[command(subcommand)]
+        command: ZoneCommand,
+    },
+    Video {
+        #[command(subcommand)]
+        command: VideoCommand,
+    },
+    Ingest {
+        #[command(subcommand)]
+        command: IngestCommand,
+    },
+    // Text of the marquee app, or a file to watch for it
+    Marquee {
+        text: Vec<String>,
+        #[arg(long)]
+        file: Option<String>,
+    },
 }
 
-#[derive(clap::ValueEnum, Debug, Clone)]
-pub enum AppName {
-    Info,
-    Fill,
-    Balls,
+#[derive(clap::Subcommand, Debug)]
+pub enum LayoutCommand {
+    // Show a marker on each client
+    Markers,
+    // Position the clients from a photo of the wall showing the markers
+    Detect { photo: String },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum MusicCommand {
+    // Play on every client, or split the channels across the given clients
+    Play {
+        path: String,
+        #[arg(long, value_delimiter = ',')]
+        clients: Vec<u8>,
+    },
+    Stop,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum PlaylistCommand {
+    // Run the apps listed in a JSON file, see playlist.rs
+    Start { path: String },
+    Stop,
+    // Skip to the next app of the loop
+    Next,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ZoneCommand {
+    // Run an app on some clients, or on the screens centered in an area of the wall
+    Add {
+        name: String,
+        app: String,
+        arguments: Vec<String>,
+        #[arg(long, value_delimiter = ',', required_unless_present = "area")]
+        clients: Vec<u8>,
+        // Left, top, right, bottom
+        #[arg(long, value_delimiter = ',', conflicts_with = "clients")]
+        area: Vec<f32>,
+    },
+    // Switch the app of a zone
+    App {
+        name: String,
+        app: String,
+        arguments: Vec<String>,
+    },
+    // Give the screens of a zone back to the main app
+    Remove {
+        name: String,
+    },
+    List,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum VideoCommand {
+    // GIF file, or directory of numbered frames
+    Play {
+        path: String,
+        #[arg(long)]
+        fps: Option<f32>,
+    },
+    Pause,
+    Resume,
+    Seek {
+        frame: usize,
+    },
+    Loop {
+        #[arg(value_parser = clap::builder::BoolishValueParser::new())]
+        enabled: bool,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum IngestCommand {
+    // Accept raw frames on a TCP socket
+    Listen {
+        address: String,
+        #[command(flatten)]
+        frame: FrameArgs,
+    },
+    // Read raw frames from a file or named pipe
+    Pipe {
+        path: String,
+        #[command(flatten)]
+        frame: FrameArgs,
+    },
+    Stop,
+}
+
+// Raw frames, the wall's total resolution by default
+#[derive(clap::Args, Debug)]
+pub struct FrameArgs {
+    #[arg(long, value_enum, default_value = "rgb")]
+    pub format: PixelFormat,
+    #[arg(long, requires = "height")]
+    pub width: Option<u32>,
+    #[arg(long, requires = "width")]
+    pub height: Option<u32>,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum PixelFormat {
+    Rgb,
+    Gray,
 }
 
 fn main() {
@@ -0,0 +1,204 @@
+use std::fs;
+use std::time::Duration;
+
+use chrono::{Local, NaiveTime};
+use serde::Deserialize;
+
+use crate::apps;
+use crate::transition::TransitionKind;
+
+// Apps to run unattended, eg.
+//
+// {
+//     "transition": "fade",
+//     "entries": [
+//         { "app": "script", "arguments": ["clock.rhai"], "from": "9:00", "until": "10:00" },
+//         { "app": "image", "arguments": ["photo1.png"], "duration": 20 },
+//         { "app": "image", "arguments": ["photo2.png"], "duration": 20 }
+//     ]
+// }
+//
+// Scheduled entries run while the local time is in their window, the others take turns for
+// their duration, in a loop unless "loop" is false.
+pub struct Playlist {
+    entries: Vec<PlaylistEntry>,
+    looping: bool,
+
+    // Entry running, and the position in the loop of unscheduled entries
+    current_index: Option<usize>,
+    looped_index: usize,
+    elapsed: Duration,
+    finished: bool,
+}
+
+#[derive(Clone)]
+pub struct PlaylistEntry {
+    pub app: String,
+    pub arguments: Vec<String>,
+    pub transition: Option<TransitionKind>,
+    duration: Duration,
+    // Time of day window, can span midnight
+    schedule: Option<(NaiveTime, NaiveTime)>,
+}
+
+#[derive(Deserialize)]
+struct PlaylistFile {
+    #[serde(default = "default_loop")]
+    r#loop: bool,
+    transition: Option<TransitionKind>,
+    entries: Vec<PlaylistFileEntry>,
+}
+
+#[derive(Deserialize)]
+struct PlaylistFileEntry {
+    app: String,
+    #[serde(default)]
+    arguments: Vec<String>,
+    transition: Option<TransitionKind>,
+    duration: Option<f32>, // s
+    from: Option<String>,
+    until: Option<String>,
+}
+
+fn default_loop() -> bool {
+    true
+}
+
+// Entries run 1 min unless told otherwise
+const DEFAULT_DURATION: Duration = Duration::from_secs(60);
+
+impl Playlist {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json_string = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let file: PlaylistFile =
+            serde_json::from_str(&json_string).map_err(|e| format!("{path}: {e}"))?;
+
+        let mut entries = Vec::with_capacity(file.entries.len());
+
+        for entry in file.entries {
+            if !apps::APPS.iter().any(|spec| spec.name == entry.app) {
+                return Err(format!("unknown app {}", entry.app));
+            }
+
+            let duration = match entry.duration {
+                Some(duration) if duration > 0.0 => Duration::from_secs_f32(duration),
+                Some(_) => return Err(format!("{}: duration must be positive", entry.app)),
+                None => DEFAULT_DURATION,
+            };
+
+            let schedule = match (&entry.from, &entry.until) {
+                (None, None) => None,
+                (from, until) => Some((
+                    parse_time(from.as_deref().unwrap_or("0:00"))?,
+                    parse_time(until.as_deref().unwrap_or("0:00"))?,
+                )),
+            };
+
+            entries.push(PlaylistEntry {
+                app: entry.app,
+                arguments: entry.arguments,
+                transition: entry.transition.or(file.transition),
+                duration,
+                schedule,
+            });
+        }
+
+        if entries.is_empty() {
+            return Err(String::from("the playlist has no entries"));
+        }
+
+        Ok(Self {
+            entries,
+            looping: file.r#loop,
+            current_index: None,
+            looped_index: 0,
+            elapsed: Duration::ZERO,
+            finished: false,
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // Entry to switch to, if it changed
+    pub fn update(&mut self, dt: &Duration) -> Option<&PlaylistEntry> {
+        self.elapsed += *dt;
+
+        let now = Local::now().time();
+
+        let target_index = match self.scheduled_index(now) {
+            Some(index) => Some(index),
+            None => {
+                // Move on once the entry has run long enough
+                let is_current_over = self.current_index.is_some_and(|index| {
+                    Some(index) == self.looped_entry_index()
+                        && self.elapsed >= self.entries[index].duration
+                });
+
+                if is_current_over {
+                    self.advance();
+                }
+
+                self.looped_entry_index()
+            }
+        };
+
+        if self.finished || target_index.is_none() || target_index == self.current_index {
+            return None;
+        }
+
+        self.current_index = target_index;
+        self.elapsed = Duration::ZERO;
+
+        target_index.map(|index| &self.entries[index])
+    }
+
+    // Skip to the next entry of the loop, scheduled entries keep running until their end
+    pub fn next(&mut self) {
+        if self.current_index.is_some() && self.current_index == self.looped_entry_index() {
+            self.advance();
+            self.current_index = None;
+        }
+    }
+
+    fn scheduled_index(&self, now: NaiveTime) -> Option<usize> {
+        self.entries.iter().position(|entry| match entry.schedule {
+            Some((from, until)) if from < until => now >= from && now < until,
+            Some((from, until)) => now >= from || now < until,
+            None => false,
+        })
+    }
+
+    // Unscheduled entry at the current position of the loop
+    fn looped_entry_index(&self) -> Option<usize> {
+        let count = self.entries.len();
+
+        (0..count)
+            .map(|offset| (self.looped_index + offset) % count)
+            .find(|index| self.entries[*index].schedule.is_none())
+    }
+
+    fn advance(&mut self) {
+        let current_index = match self.looped_entry_index() {
+            Some(index) => index,
+            None => return,
+        };
+
+        let next_index = (current_index + 1..self.entries.len())
+            .find(|index| self.entries[*index].schedule.is_none());
+
+        match next_index {
+            Some(index) => self.looped_index = index,
+            None if self.looping => self.looped_index = 0,
+            None => self.finished = true,
+        }
+    }
+}
+
+// 24h times, eg. 9:00 or 21:30:15
+fn parse_time(time: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+        .map_err(|_| format!("invalid time {time}, expected eg. 9:00 or 21:30"))
+}
@@ -1,12 +1,15 @@
-use crate::apps::display_image::DisplayImageApp;
-use crate::ServerCommand;
-use crate::{
-    apps::{
-        bouncing_balls::BouncingBallsApp, fill_screens::FillScreensApp, show_info::ShowInfoApp, App,
-    },
-    clients::client::Client,
-    AppName,
-};
+use crate::apps::{self, bouncing_balls::BouncingBallsApp, markers::MarkersApp, App};
+use crate::clients::client::Client;
+use crate::engine::dithering::Conversion;
+use crate::engine::markers;
+use crate::engine::music::{MusicOutput, MusicPlayer, Song};
+use crate::playlist::Playlist;
+use crate::transition::{Transition, TransitionKind};
+use crate::zone::{self, Zone, ZoneScreens};
+use crate::{LayoutCommand, MusicCommand, PlaylistCommand, ServerCommand, ZoneCommand};
+use parry2d::bounding_volume::AABB;
+use parry2d::math::Point;
+use std::collections::HashSet;
 use std::sync::mpsc::{Sender, TryRecvError};
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread::{self, JoinHandle};
@@ -23,8 +26,15 @@ pub struct Server {
     connection_thread_handle: Option<JoinHandle<()>>,
     connection_thread_channel: Option<Sender<u8>>,
     clients: Arc<Mutex<Vec<Client>>>,
+    known_client_ids: HashSet<u8>,
 
     app: Box<dyn App>,
+    transition: Option<Transition>,
+    playlist: Option<Playlist>,
+    zones: Vec<Zone>,
+    music: Option<MusicPlayer>,
+    // Of the tiles and sprites, images are converted by their app
+    conversion: Conversion,
 }
 
 impl Server {
@@ -37,7 +47,13 @@ impl Server {
             connection_thread_handle: Option::None,
             connection_thread_channel: Option::None,
             clients: Arc::new(Mutex::new(Vec::new())),
+            known_client_ids: HashSet::new(),
             app: Box::new(BouncingBallsApp::new()),
+            transition: None,
+            playlist: None,
+            zones: Vec::new(),
+            music: None,
+            conversion: Conversion::default(),
         }
     }
 
@@ -61,6 +77,8 @@ impl Server {
     pub fn start(&mut self, address: &str) {
         println!("Starting server");
 
+        self.app.on_start(&mut self.clients.lock().unwrap());
+
         let a = String::from(address);
 
         let concurrent_clients = self.clients.clone();
@@ -132,13 +150,339 @@ impl Server {
         let dt = now - self.last_update_time;
         self.last_update_time = now;
 
-        self.app.update(&dt, &mut self.clients.lock().unwrap());
+        self.update_playlist(&dt);
 
-        for client in self.clients.lock().unwrap().iter_mut() {
+        let mut clients = self.clients.lock().unwrap();
+
+        // Tell the apps about the clients that left since the last update
+
+        for client in clients.iter().filter(|client| !client.is_connected()) {
+            if self.known_client_ids.remove(&client.id()) {
+                self.app.on_client_left(client.id());
+            }
+
+            for zone in self.zones.iter_mut() {
+                if zone.known_client_ids.remove(&client.id()) {
+                    zone.app.on_client_left(client.id());
+                }
+            }
+        }
+
+        clients.retain(|client| client.is_connected());
+
+        for client in clients.iter_mut() {
+            client.set_conversion(&self.conversion);
+        }
+
+        // Split the clients between the zones, the rest of the wall runs the main app
+
+        let mut zone_clients: Vec<Vec<Client>> = self.zones.iter().map(|_| Vec::new()).collect();
+        let mut rest_clients = Vec::new();
+
+        for client in clients.drain(..) {
+            match zone::zone_index(&self.zones, &client) {
+                Some(index) => zone_clients[index].push(client),
+                None => rest_clients.push(client),
+            }
+        }
+
+        // Tell the apps about the clients that moved to another zone or joined, the moved
+        // screens are cleared for their new app
+
+        let mut moved_client_ids =
+            remove_absent_clients(&mut self.app, &mut self.known_client_ids, &rest_clients);
+
+        for (zone, clients) in self.zones.iter_mut().zip(&zone_clients) {
+            moved_client_ids.extend(remove_absent_clients(
+                &mut zone.app,
+                &mut zone.known_client_ids,
+                clients,
+            ));
+        }
+
+        add_new_clients(
+            &mut self.app,
+            &mut self.known_client_ids,
+            &mut rest_clients,
+            &moved_client_ids,
+        );
+
+        for (zone, clients) in self.zones.iter_mut().zip(zone_clients.iter_mut()) {
+            add_new_clients(
+                &mut zone.app,
+                &mut zone.known_client_ids,
+                clients,
+                &moved_client_ids,
+            );
+        }
+
+        // Dispatch the inputs received since the last update, unless the app is being covered
+
+        let is_app_frozen = self
+            .transition
+            .as_ref()
+            .is_some_and(|transition| transition.freezes_app());
+
+        for client in rest_clients.iter_mut() {
+            for event in client.poll_input() {
+                if !is_app_frozen {
+                    self.app.on_input(client.id(), &event);
+                }
+            }
+        }
+
+        if !is_app_frozen {
+            self.app.update(&dt, &mut rest_clients);
+        }
+
+        // Switch to the incoming app once the outgoing one is covered
+
+        if let Some(transition) = &mut self.transition {
+            if let Some(app) = transition.update(&dt, &mut rest_clients) {
+                replace_app(
+                    &mut self.app,
+                    &mut self.known_client_ids,
+                    app,
+                    &mut rest_clients,
+                );
+                transition.start_reveal(&mut rest_clients);
+            }
+
+            if transition.is_done() {
+                self.transition = None;
+            }
+        }
+
+        // The zones' apps
+
+        for (zone, clients) in self.zones.iter_mut().zip(zone_clients.iter_mut()) {
+            for client in clients.iter_mut() {
+                for event in client.poll_input() {
+                    zone.app.on_input(client.id(), &event);
+                }
+            }
+
+            zone.app.update(&dt, clients);
+        }
+
+        clients.extend(rest_clients);
+        clients.extend(zone_clients.into_iter().flatten());
+
+        // Music commands are sent with the app's, so that all the clients play in sync
+
+        if let Some(music) = &mut self.music {
+            music.update(&dt, &mut clients);
+        }
+
+        for client in clients.iter_mut() {
             client.send_commands();
         }
     }
 
+    // Switch to the playlist's next app when it's time
+    fn update_playlist(&mut self, dt: &Duration) {
+        let playlist = match &mut self.playlist {
+            Some(playlist) => playlist,
+            None => return,
+        };
+
+        let entry = playlist.update(dt).cloned();
+
+        if playlist.is_finished() {
+            println!("playlist finished");
+            self.playlist = None;
+        }
+
+        if let Some(entry) = entry {
+            match apps::create_app(&entry.app, &entry.arguments) {
+                Ok(app) => {
+                    println!("playlist: switching app to {}", entry.app);
+                    self.start_app(app, entry.transition);
+                }
+                // Keep the current app until the next entry
+                Err(e) => println!("playlist: {}", e),
+            }
+        }
+    }
+
+    fn start_app(&mut self, app: Box<dyn App>, transition: Option<TransitionKind>) {
+        match transition {
+            Some(kind) => self.transition = Some(Transition::new(kind, app)),
+            None => self.switch_app(app),
+        }
+    }
+
+    fn switch_app(&mut self, app: Box<dyn App>) {
+        // Cancel the current transition, if any
+        self.transition = None;
+
+        // Only on the screens out of the zones
+
+        let mut clients = self.clients.lock().unwrap();
+        let mut rest_clients = take_clients(&mut clients, |client| {
+            zone::zone_index(&self.zones, client).is_none()
+        });
+
+        replace_app(
+            &mut self.app,
+            &mut self.known_client_ids,
+            app,
+            &mut rest_clients,
+        );
+
+        clients.extend(rest_clients);
+    }
+
+    fn process_zone_command(&mut self, command: &ZoneCommand) {
+        match command {
+            ZoneCommand::Add {
+                name,
+                app,
+                arguments,
+                clients,
+                area,
+            } => {
+                if self.zones.iter().any(|zone| zone.name == *name) {
+                    println!("zone {} already exists", name);
+                    return;
+                }
+
+                let screens = match area[..] {
+                    [] => ZoneScreens::Clients(clients.clone()),
+                    [left, top, right, bottom] => ZoneScreens::Area(AABB::new(
+                        Point::new(left, top),
+                        Point::new(right, bottom),
+                    )),
+                    _ => {
+                        println!("the area must be left,top,right,bottom");
+                        return;
+                    }
+                };
+
+                let app_name = app;
+                let app = match apps::create_app(app_name, arguments) {
+                    Ok(app) => app,
+                    Err(e) => {
+                        println!("{}", e);
+                        return;
+                    }
+                };
+
+                let zone_index = self.zones.len();
+                self.zones.push(Zone::new(name, screens, app_name, app));
+
+                // Take the screens from the rest of the wall
+
+                let mut clients = self.clients.lock().unwrap();
+                let mut zone_clients = take_clients(&mut clients, |client| {
+                    zone::zone_index(&self.zones, client) == Some(zone_index)
+                });
+
+                let zone = &mut self.zones[zone_index];
+
+                for client in zone_clients.iter_mut() {
+                    client.reset();
+                }
+
+                zone.app.on_start(&mut zone_clients);
+                zone.known_client_ids = zone_clients.iter().map(|client| client.id()).collect();
+
+                println!(
+                    "zone {} runs {} on {} ({} clients)",
+                    zone.name,
+                    zone.app_name,
+                    zone.screens,
+                    zone_clients.len()
+                );
+
+                clients.extend(zone_clients);
+            }
+
+            ZoneCommand::App {
+                name,
+                app,
+                arguments,
+            } => {
+                let zone_index = match self.zones.iter().position(|zone| zone.name == *name) {
+                    Some(index) => index,
+                    None => {
+                        println!("unknown zone {}", name);
+                        return;
+                    }
+                };
+
+                let app_name = app;
+                let app = match apps::create_app(app_name, arguments) {
+                    Ok(app) => app,
+                    Err(e) => {
+                        println!("{}", e);
+                        return;
+                    }
+                };
+
+                let mut clients = self.clients.lock().unwrap();
+                let mut zone_clients = take_clients(&mut clients, |client| {
+                    zone::zone_index(&self.zones, client) == Some(zone_index)
+                });
+
+                let zone = &mut self.zones[zone_index];
+
+                println!("switching zone {} to {}", zone.name, app_name);
+
+                replace_app(
+                    &mut zone.app,
+                    &mut zone.known_client_ids,
+                    app,
+                    &mut zone_clients,
+                );
+                zone.app_name = app_name.clone();
+
+                clients.extend(zone_clients);
+            }
+
+            ZoneCommand::Remove { name } => {
+                let zone_index = match self.zones.iter().position(|zone| zone.name == *name) {
+                    Some(index) => index,
+                    None => {
+                        println!("unknown zone {}", name);
+                        return;
+                    }
+                };
+
+                // Give the screens back to the rest of the wall, blank
+
+                let mut clients = self.clients.lock().unwrap();
+                let mut zone_clients = take_clients(&mut clients, |client| {
+                    zone::zone_index(&self.zones, client) == Some(zone_index)
+                });
+
+                let mut zone = self.zones.remove(zone_index);
+
+                zone.app.on_stop(&mut zone_clients);
+
+                for client in zone_clients.iter_mut() {
+                    client.reset();
+                }
+
+                println!("removed zone {}", zone.name);
+
+                clients.extend(zone_clients);
+            }
+
+            ZoneCommand::List => {
+                for zone in &self.zones {
+                    println!(
+                        "{:<10} {} on {} ({} clients)",
+                        zone.name,
+                        zone.app_name,
+                        zone.screens,
+                        zone.known_client_ids.len()
+                    );
+                }
+            }
+        }
+    }
+
     pub fn process_command(&mut self, command: &ServerCommand) {
         match command {
             ServerCommand::Quit => {
@@ -146,14 +490,98 @@ impl Server {
                 self.running = false;
             }
 
-            ServerCommand::App { app } => {
-                println!("switching app to {:?}", app);
+            ServerCommand::App {
+                name,
+                arguments,
+                transition,
+            } => match name {
+                Some(name) => match apps::create_app(name, arguments) {
+                    Ok(app) => {
+                        // Picking an app by hand takes over from the playlist
+                        if self.playlist.take().is_some() {
+                            println!("stopping the playlist");
+                        }
 
-                self.app = match app {
-                    AppName::Info => Box::new(ShowInfoApp::new()),
-                    AppName::Fill => Box::new(FillScreensApp::new()),
-                    AppName::Balls => Box::new(BouncingBallsApp::new()),
-                };
+                        println!("switching app to {}", name);
+                        self.start_app(app, *transition);
+                    }
+                    Err(e) => println!("{}", e),
+                },
+                None => apps::print_apps(),
+            },
+
+            ServerCommand::Layout { command } => match command {
+                LayoutCommand::Markers => {
+                    println!("showing the layout markers");
+                    self.switch_app(Box::new(MarkersApp::new()));
+                }
+                LayoutCommand::Detect { photo } => match image::open(photo) {
+                    Ok(photo) => {
+                        let detected_markers = markers::detect_markers(&photo);
+                        let mut clients = self.clients.lock().unwrap();
+
+                        let found = markers::apply_layout(&detected_markers, &mut clients);
+
+                        println!("found {} of {} clients", found, clients.len());
+                    }
+                    Err(e) => {
+                        println!("Cannot load photo {}: {}", photo, e);
+                    }
+                },
+            },
+
+            ServerCommand::Zone { command } => self.process_zone_command(command),
+
+            ServerCommand::Playlist { command } => match command {
+                PlaylistCommand::Start { path } => match Playlist::load(path) {
+                    Ok(playlist) => {
+                        println!("starting playlist {}", path);
+                        self.playlist = Some(playlist);
+                    }
+                    Err(e) => println!("Cannot load playlist {}: {}", path, e),
+                },
+                PlaylistCommand::Stop => {
+                    if self.playlist.take().is_some() {
+                        println!("stopping the playlist");
+                    }
+                }
+                PlaylistCommand::Next => match &mut self.playlist {
+                    Some(playlist) => playlist.next(),
+                    None => println!("no playlist running"),
+                },
+            },
+
+            ServerCommand::Dither { .. } => {
+                if let Some(conversion) = Conversion::from_command(command) {
+                    self.conversion = conversion;
+                }
+            }
+
+            ServerCommand::Music { command } => {
+                let mut clients = self.clients.lock().unwrap();
+
+                if let Some(music) = self.music.take() {
+                    music.stop(&mut clients);
+                }
+
+                if let MusicCommand::Play { path, clients } = command {
+                    match Song::load(path) {
+                        Ok(song) => {
+                            println!("playing {}", path);
+
+                            let output = if clients.is_empty() {
+                                MusicOutput::All
+                            } else {
+                                MusicOutput::Clients(clients.clone())
+                            };
+
+                            self.music = Some(MusicPlayer::new(song, output));
+                        }
+                        Err(e) => {
+                            println!("Cannot load song {}: {}", path, e);
+                        }
+                    }
+                }
             }
 
             _ => {}
@@ -165,8 +593,77 @@ impl Server {
             client.process_server_command(command);
         }
 
-        // Forward to the app
+        // Forward to the apps
 
         self.app.process_server_command(command);
+
+        for zone in self.zones.iter_mut() {
+            zone.app.process_server_command(command);
+        }
+    }
+}
+
+// Clients matching a filter, removed from the list
+fn take_clients(clients: &mut Vec<Client>, filter: impl Fn(&Client) -> bool) -> Vec<Client> {
+    let (taken_clients, kept_clients) = clients.drain(..).partition(|client| filter(client));
+    *clients = kept_clients;
+    taken_clients
+}
+
+// Tell an app about the clients it no longer runs on, returns their IDs
+fn remove_absent_clients(
+    app: &mut Box<dyn App>,
+    known_client_ids: &mut HashSet<u8>,
+    clients: &[Client],
+) -> HashSet<u8> {
+    let absent_client_ids: HashSet<u8> = known_client_ids
+        .iter()
+        .copied()
+        .filter(|client_id| !clients.iter().any(|client| client.id() == *client_id))
+        .collect();
+
+    for client_id in absent_client_ids.iter() {
+        known_client_ids.remove(client_id);
+        app.on_client_left(*client_id);
+    }
+
+    absent_client_ids
+}
+
+// Tell an app about its new clients, clearing the screens that still show another app
+fn add_new_clients(
+    app: &mut Box<dyn App>,
+    known_client_ids: &mut HashSet<u8>,
+    clients: &mut [Client],
+    moved_client_ids: &HashSet<u8>,
+) {
+    for client in clients.iter_mut() {
+        if known_client_ids.insert(client.id()) {
+            if moved_client_ids.contains(&client.id()) {
+                client.reset();
+            }
+
+            app.on_client_joined(client);
+        }
     }
 }
+
+// Clear the screens between the apps, so that the new one starts from a known state
+fn replace_app(
+    app: &mut Box<dyn App>,
+    known_client_ids: &mut HashSet<u8>,
+    new_app: Box<dyn App>,
+    clients: &mut Vec<Client>,
+) {
+    app.on_stop(clients);
+
+    for client in clients.iter_mut() {
+        client.reset();
+    }
+
+    *app = new_app;
+    app.on_start(clients);
+
+    // The clients it knows about
+    *known_client_ids = clients.iter().map(|client| client.id()).collect();
+}
@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use parry2d::math::Point;
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+
+use crate::apps::App;
+use crate::clients::client::Client;
+use crate::engine::color::WHITE;
+use crate::engine::tile::Tile;
+use crate::engine::world::World;
+
+// Covering the outgoing app, then revealing the incoming one
+const DURATION: Duration = Duration::from_millis(1000);
+
+#[derive(clap::ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransitionKind {
+    // Through white, with the palettes
+    Fade,
+    // Blank tiles sweeping across the wall, towards the given side
+    WipeLeft,
+    WipeRight,
+    WipeUp,
+    WipeDown,
+    // Blank tiles in random order
+    Dissolve,
+}
+
+// Switch between two apps. Fades keep the outgoing app running and fade the incoming one in,
+// the other transitions freeze the outgoing app while blanking it, the incoming one then
+// starts on the blank screens.
+pub struct Transition {
+    kind: TransitionKind,
+    elapsed: Duration,
+
+    // Until the outgoing app is covered
+    incoming: Option<Box<dyn App>>,
+
+    // Blank background cells drawn over the outgoing app, in order
+    world: World,
+    cells: Vec<Point<i32>>,
+    covered_cells: usize,
+}
+
+impl Transition {
+    pub fn new(kind: TransitionKind, incoming: Box<dyn App>) -> Self {
+        Self {
+            kind,
+            elapsed: Duration::ZERO,
+            incoming: Some(incoming),
+            world: World::new(),
+            cells: Vec::new(),
+            covered_cells: 0,
+        }
+    }
+
+    pub fn freezes_app(&self) -> bool {
+        self.incoming.is_some() && self.kind != TransitionKind::Fade
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.incoming.is_none() && self.elapsed >= DURATION
+    }
+
+    // Returns the incoming app once the outgoing one is covered, to switch to it
+    pub fn update(&mut self, dt: &Duration, clients: &mut Vec<Client>) -> Option<Box<dyn App>> {
+        self.elapsed += *dt;
+
+        let half_duration = DURATION / 2;
+
+        if self.incoming.is_some() {
+            let progress = (self.elapsed.as_secs_f32() / half_duration.as_secs_f32()).min(1.0);
+
+            match self.kind {
+                TransitionKind::Fade => {
+                    for client in clients.iter_mut() {
+                        client.fade(progress);
+                    }
+                }
+                _ => self.cover(progress, clients),
+            }
+
+            if self.elapsed >= half_duration {
+                return self.incoming.take();
+            }
+        } else if self.kind == TransitionKind::Fade {
+            let progress = (self.elapsed.saturating_sub(half_duration).as_secs_f32()
+                / half_duration.as_secs_f32())
+            .min(1.0);
+
+            for client in clients.iter_mut() {
+                client.fade(1.0 - progress);
+            }
+        }
+
+        None
+    }
+
+    // Right after the switch, before the incoming app draws anything
+    pub fn start_reveal(&mut self, clients: &mut [Client]) {
+        if self.kind == TransitionKind::Fade {
+            for client in clients.iter_mut() {
+                client.fade(1.0);
+            }
+        }
+    }
+
+    fn cover(&mut self, progress: f32, clients: &mut Vec<Client>) {
+        if clients.is_empty() {
+            return;
+        }
+
+        let area = *self.world.fit_client_screens(clients);
+
+        // The cells of the wall, in the order they are covered
+
+        if self.cells.is_empty() {
+            let (min_cell, max_cell) = (
+                self.world.cell_at(&area.mins),
+                self.world.cell_at(&area.maxs),
+            );
+
+            for y in min_cell.y..=max_cell.y {
+                for x in min_cell.x..=max_cell.x {
+                    self.cells.push(Point::new(x, y));
+                }
+            }
+
+            match self.kind {
+                TransitionKind::WipeLeft => self.cells.sort_by_key(|cell| -cell.x),
+                TransitionKind::WipeRight => self.cells.sort_by_key(|cell| cell.x),
+                TransitionKind::WipeUp => self.cells.sort_by_key(|cell| -cell.y),
+                TransitionKind::WipeDown => self.cells.sort_by_key(|cell| cell.y),
+                TransitionKind::Dissolve => self.cells.shuffle(&mut rand::thread_rng()),
+                TransitionKind::Fade => {}
+            }
+        }
+
+        let target_cells = (progress * self.cells.len() as f32).ceil() as usize;
+
+        for cell in &self.cells[self.covered_cells.min(target_cells)..target_cells] {
+            self.world.set_background_tile(*cell, &BLANK_TILE);
+        }
+
+        self.covered_cells = self.covered_cells.max(target_cells);
+        self.world.sync_clients(clients);
+    }
+}
+
+lazy_static! {
+    static ref BLANK_TILE: Tile = Tile::filled(8, 8, WHITE);
+}
@@ -0,0 +1,63 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use parry2d::bounding_volume::AABB;
+
+use crate::apps::App;
+use crate::clients::client::Client;
+
+// Screens of a zone, picked by client or by the part of the wall their center is in
+pub enum ZoneScreens {
+    Clients(Vec<u8>),
+    Area(AABB),
+}
+
+impl fmt::Display for ZoneScreens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZoneScreens::Clients(client_ids) => write!(f, "clients {client_ids:?}"),
+            ZoneScreens::Area(area) => write!(
+                f,
+                "area ({}, {}) to ({}, {})",
+                area.mins.x, area.mins.y, area.maxs.x, area.maxs.y
+            ),
+        }
+    }
+}
+
+// Group of screens running its own app, apart from the rest of the wall
+pub struct Zone {
+    pub name: String,
+    pub screens: ZoneScreens,
+
+    pub app_name: String,
+    pub app: Box<dyn App>,
+    // The clients the app knows about
+    pub known_client_ids: HashSet<u8>,
+}
+
+impl Zone {
+    pub fn new(name: &str, screens: ZoneScreens, app_name: &str, app: Box<dyn App>) -> Self {
+        Self {
+            name: name.to_string(),
+            screens,
+            app_name: app_name.to_string(),
+            app,
+            known_client_ids: HashSet::new(),
+        }
+    }
+
+    pub fn contains(&self, client: &Client) -> bool {
+        match &self.screens {
+            ZoneScreens::Clients(client_ids) => client_ids.contains(&client.id()),
+            ZoneScreens::Area(area) => {
+                area.contains_local_point(&client.screen().bounding_box().center())
+            }
+        }
+    }
+}
+
+// Zone of a client, the first one containing it if they overlap, none for the rest of the wall
+pub fn zone_index(zones: &[Zone], client: &Client) -> Option<usize> {
+    zones.iter().position(|zone| zone.contains(client))
+}